@@ -1,61 +1,247 @@
 use crossterm::event::KeyCode;
 use crate::events::EventState;
 use crate::config::Config;
-use crate::message::{Message, Header};
+use crate::message::{self, Message, BATCH_MODIFY_LIMIT};
+use crate::error::{Error, Result};
+use crate::data::find_undo_entry;
+use crate::backend::{self, FetchedMetadata, LabelChange, MailBackend, Synced};
+use crate::report::DryRunReport;
+use crate::store::Store;
+use crate::cache::MetadataCache;
+use crate::group::{self, FromRow, Grouping, SortOrder};
 
 use tui::{
     Frame,
     backend::Backend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
     widgets::{
-        Block, BorderType, Borders, List, ListItem, ListState, Tabs,
+        Block, BorderType, Borders, Cell, Gauge, List, ListItem, ListState, Row, Table, TableState, Tabs,
     },
 };
+use chrono::{DateTime, Local, Utc};
 
-use chrono::Local;
+use tokio::sync::mpsc;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use std::fs;
+// 行の未読のmessage_listの位置と受信日時（新しい順）
+type RowIndexes = Vec<(usize, Option<DateTime<Utc>>)>;
 
 pub struct App<'a> {
     config: &'a Config<'a>,
-    message_list: &'a Vec<Message>,
+    // Targetルール・ログ・履歴
+    store: Store<'a>,
+    backend: Arc<dyn MailBackend>,
+    message_list: Vec<Message>,
     list_state: ListStates,
-    address_list: &'a Vec<&'a str>,
-    count_list: &'a Vec<&'a str>,
+    focus: Focus,
+    // Fromペインのまとめ方と展開中のグループ
+    grouping: Grouping,
+    expanded: HashSet<String>,
+    sort_order: SortOrder,
+    // "/" で入力する絞り込み文字列と入力中かどうか
+    filter: String,
+    searching: bool,
+    from_rows: Vec<FromRow>,
+    // Executeの対象になるメッセージの送信者
+    // ルール・メッセージ・選択が変わったときだけ計算し直す（絞り込みや並べ替えでは変わらない）
+    targeted: HashSet<String>,
+    // Enterで開いた送信者の行と、1通ずつ選んだメッセージのID
+    opened: Option<FromRow>,
+    selected_ids: HashSet<String>,
+    // 開いた行・選択中の行の未読（message_listの位置と受信日時、新しい順）
+    // 行の選択が変わったときとFromペインを作り直したときだけ作り直す
+    row_cache: HashMap<String, RowIndexes>,
+    execution: Option<Execution>,
+    // 未読リストを同期したときの履歴ID
+    history_id: Option<String>,
+    // 裏で動かしたAPI呼び出しの結果の送り先
+    tasks: mpsc::UnboundedSender<TaskResult>,
+    // 未読リストを同期中かどうか
+    refreshing: bool,
+    last_refresh: Instant,
+    // 同期を始めたときの未読のIDと送信者のアドレス
+    // 同期中に届いたページに左右されずに未読数の変化を比べる
+    refresh_base: Vec<(String, String)>,
+    // 前回の同期で未読数が変わった送信者のアドレス
+    changed_senders: HashSet<String>,
+    // このセッションで既読にしたメッセージ（取得し直した未読リストから除く）
+    read_ids: HashSet<String>,
+    // Executeで既読化せずレポートだけ出す
+    dry_run: bool,
+    // 選択中の送信者の未読を表示する
+    preview: bool,
+    // ログファイルに書き込めなかったときのエラー
+    unlogged_error: Option<String>,
+}
+
+// 操作対象のペイン
+#[derive(PartialEq)]
+pub enum Focus {
+    From,
+    Target,
+    // 開いた送信者のメッセージ一覧
+    Messages,
+}
+
+// 裏で動かしたAPI呼び出しの結果
+pub enum TaskResult {
+    // 全件取得中に届いた1ページ分の未読
+    RefreshPage(Vec<Message>),
+    // 未読リストの同期
+    Refresh(Result<Synced>),
+    // Execute・Undoの1チャンク分のラベル変更
+    Chunk(Vec<String>, Result<()>),
+    // Undoで未読に戻したメッセージのメタデータ
+    Fetched(Result<FetchedMetadata>),
+}
+
+// Execute・Undo実行中の進捗
+pub struct Execution {
+    change: LabelChange,
+    // 対象アドレスとそのメッセージID
+    targets: Vec<(String, Vec<String>)>,
+    // Executeは記録中の履歴、Undoは取り消す履歴の位置
+    journal_index: Option<usize>,
+    chunks: VecDeque<Vec<String>>,
+    chunk_count: usize,
+    processed: usize,
+    succeeded: Vec<String>,
+    failed: usize,
 }
 
 impl<'a> App<'a> {
     pub fn new(
         config: &'a Config<'a>,
-        message_list: &'a Vec<Message>,
-        address_list: &'a Vec<&'a str>,
-        count_list: &'a Vec<&'a str>
+        store: Store<'a>,
+        backend: Arc<dyn MailBackend>,
+        cache: MetadataCache,
+        tasks: mpsc::UnboundedSender<TaskResult>,
     ) -> Self {
         // From選択構造体
-        let mut from_list_state = ListState::default();
-        from_list_state.select(Some(0));
+        let mut from_table_state = TableState::default();
+        from_table_state.select(Some(0));
 
-        // Count選択構造体
-        let mut count_list_state = ListState::default();
-        count_list_state.select(Some(0));
+        // Target選択構造体
+        let mut target_list_state = ListState::default();
+        target_list_state.select(Some(0));
 
         let list_state = ListStates::new(
-            from_list_state,
-            count_list_state
+            from_table_state,
+            target_list_state,
         );
 
-        Self {
+        let mut app = Self {
             config,
-            message_list,
+            store,
+            backend,
+            message_list: cache.messages(),
             list_state,
-            address_list,
-            count_list
+            focus: Focus::From,
+            grouping: Grouping::Address,
+            expanded: HashSet::new(),
+            sort_order: SortOrder::Count,
+            filter: String::new(),
+            searching: false,
+            from_rows: Vec::new(),
+            targeted: HashSet::new(),
+            opened: None,
+            selected_ids: HashSet::new(),
+            row_cache: HashMap::new(),
+            execution: None,
+            history_id: cache.history_id().map(|id| id.to_string()),
+            tasks,
+            refreshing: false,
+            last_refresh: Instant::now(),
+            refresh_base: Vec::new(),
+            changed_senders: HashSet::new(),
+            read_ids: HashSet::new(),
+            dry_run: false,
+            preview: false,
+            unlogged_error: None,
+        };
+        app.update_targets();
+
+        app
+    }
+
+    // Executeの対象を計算し直してからFromペインの行を作り直す
+    fn update_targets(&mut self) {
+        self.targeted = self.targeted_addresses();
+        self.update_lists();
+    }
+
+    // メッセージリストからFromペインの行を作り直す
+    fn update_lists(&mut self) {
+        self.row_cache.clear();
+        let sender_count_list = message::get_sender_count_list(&self.message_list);
+        let selected_id = self.selected_from_row().map(|r| r.id());
+
+        self.from_rows = group::build_rows(
+            sender_count_list,
+            self.grouping,
+            self.sort_order,
+            &self.expanded,
+            &self.filter,
+            &self.targeted,
+        );
+
+        // 並べ替えや絞り込みの後も同じ行を選択したままにする
+        if let Some(i) = selected_id.and_then(|id| self.from_rows.iter().position(|r| r.id() == id)) {
+            self.list_state.from.select(Some(i));
         }
+
+        // 選択位置がリストからはみ出さないように
+        let selected = clamp_index(self.list_state.from.selected(), self.from_rows.len());
+        self.list_state.from.select(selected);
+    }
+
+    // Executeの対象
+    // Targetルールに一致するものに、1通ずつ選んだメッセージを加える
+    fn execute_targets(&self) -> Result<Vec<(String, Vec<String>)>> {
+        let mut targets = message::collect_targets(&self.message_list, self.store.rules())?;
+
+        // ルールで対象になっているものは除く
+        let collected = targets.iter()
+            .flat_map(|(_, ids)| ids.iter().map(String::as_str))
+            .collect::<HashSet<&str>>();
+        let ids = self.message_list.iter()
+            .filter_map(|m| m.id.as_deref())
+            .filter(|id| self.selected_ids.contains(*id) && !collected.contains(id))
+            .map(|id| id.to_string())
+            .collect::<Vec<String>>();
+        if !ids.is_empty() {
+            targets.push(("selected messages".to_string(), ids));
+        }
+
+        Ok(targets)
+    }
+
+    // Executeの対象になるメッセージの送信者
+    // ルールが読めなくても一覧は出す
+    fn targeted_addresses(&self) -> HashSet<String> {
+        let targets = self.execute_targets().unwrap_or_default();
+        let id_list = targets.iter()
+            .flat_map(|(_, ids)| ids.iter().map(AsRef::as_ref))
+            .collect::<HashSet<&str>>();
+
+        self.message_list.iter()
+            .filter(|m| m.id.as_deref().is_some_and(|id| id_list.contains(id)))
+            .filter_map(|m| m.sender())
+            .map(|s| s.address)
+            .collect()
     }
 
     pub fn draw<B: Backend>(&mut self, f: &mut Frame<'_, B>) {
+        self.cache_row_messages();
+
         // 縦方向分割
         let vertical_chunk = Layout::default()
             .direction(Direction::Vertical)
@@ -70,218 +256,999 @@ impl<'a> App<'a> {
             )
             .split(f.size());
 
-        let menu = vec!["Add", "Delete", "Execute", "Quit"]
-            .iter()
-            .map(|t| {
-                let (first, rest) = t.split_at(1);
-                Spans::from(vec![
-                    Span::styled(
-                        first,
-                        Style::default()
-                            .fg(Color::Yellow)
-                            .add_modifier(Modifier::UNDERLINED),
-                    ),
-                    Span::styled(rest, Style::default().fg(Color::White)),
-                ])
-            })
-            .collect();
+        if let Some(execution) = &self.execution {
+            // 実行中はメニューの代わりに進捗を表示
+            let percent = execution.processed * 100 / execution.chunk_count;
+            let gauge = Gauge::default()
+                .block(Block::default().title(execution.change.name()).borders(Borders::ALL))
+                .gauge_style(Style::default().fg(Color::Yellow))
+                .percent(percent as u16)
+                .label(format!("{}/{} chunks", execution.processed, execution.chunk_count));
+            f.render_widget(gauge, vertical_chunk[0]);
+        } else {
+            // (表示名, キーの位置)
+            let dry_run = if self.dry_run { "Dry-run: on" } else { "Dry-run: off" };
+            let grouping = format!("Group: {}", self.grouping.name());
+            let sort_order = format!("Sort: {}", self.sort_order.name());
+            let menu = vec![
+                ("Add", 0),
+                ("Delete", 0),
+                ("Execute", 0),
+                ("Undo", 0),
+                (dry_run, 2),
+                (grouping.as_str(), 0),
+                (sort_order.as_str(), 0),
+                ("Preview", 0),
+                ("Refresh", 0),
+                ("Quit", 0),
+            ]
+                .into_iter()
+                .map(|(t, key)| {
+                    let (head, rest) = t.split_at(key);
+                    let (key, tail) = rest.split_at(1);
+                    Spans::from(vec![
+                        Span::styled(head, Style::default().fg(Color::White)),
+                        Span::styled(
+                            key,
+                            Style::default()
+                                .fg(Color::Yellow)
+                                .add_modifier(Modifier::UNDERLINED),
+                        ),
+                        Span::styled(tail, Style::default().fg(Color::White)),
+                    ])
+                })
+                .collect();
 
-        // 上部メニュー
-        let tabs = Tabs::new(menu)
-            .block(Block::default().title("Menu").borders(Borders::ALL))
-            .style(Style::default().fg(Color::White))
-            .divider(Span::raw("|"));
-        f.render_widget(tabs, vertical_chunk[0]);
-
-        let read = fs::read_to_string(self.config.log_path).unwrap();
-        let mut log_list: Vec<String> = Vec::new();
-        if !read.is_empty() {
-            log_list = serde_json::from_str(&read).unwrap();
+            // 上部メニュー
+            let tabs = Tabs::new(menu)
+                .block(Block::default().title("Menu").borders(Borders::ALL))
+                .style(Style::default().fg(Color::White))
+                .divider(Span::raw("|"));
+            f.render_widget(tabs, vertical_chunk[0]);
         }
 
+        // 書き込めなかった場合はその旨をLogsに出す
+        let unlogged_error = self.unlogged_error.as_ref().map(|err| format!("Error: {}", err));
+        let log_list = self.store.logs().iter()
+            .chain(unlogged_error.iter())
+            .map(AsRef::as_ref)
+            .collect();
+
         // 下部ロガー
         let logs = render_list_items(
             "Logs",
-            log_list.iter().map(AsRef::as_ref).collect(),
+            log_list,
+            false,
         );
         f.render_widget(logs, vertical_chunk[2]);
 
         // 中央
+        // プレビュー表示中は下にプレビューを置く
+        let middle_chunk = if self.preview {
+            Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(5), Constraint::Length(10)].as_ref())
+                .split(vertical_chunk[1])
+        } else {
+            vec![vertical_chunk[1]]
+        };
+        if self.preview {
+            self.draw_preview(f, middle_chunk[1]);
+        }
+
         // 横方向分割
         let horizon_chunk = Layout::default()
             .direction(Direction::Horizontal)
             .constraints(
                 [
-                    Constraint::Percentage(47),
-                    Constraint::Percentage(6),
-                    Constraint::Percentage(47),
+                    Constraint::Percentage(60),
+                    Constraint::Percentage(40),
                 ].as_ref(),
             )
-            .split(vertical_chunk[1]);
+            .split(middle_chunk[0]);
 
-        // 左部Fromリスト
-        let left = render_list_items(
-            "From",
-            self.address_list.clone(), // ループするクロージャごとでmoveされるため
-        );
-        f.render_stateful_widget(left, horizon_chunk[0], &mut self.list_state.from);
-
-        let mid = render_list_items(
-            "Count",
-            self.count_list.clone(), // ループするクロージャごとでmoveされるため
-        );
-        f.render_stateful_widget(mid, horizon_chunk[1], &mut self.list_state.count);
-
-        let read = fs::read_to_string(self.config.mark_list_path).unwrap();
-
-        let mut target_list: Vec<String> = Vec::new();
-        if !read.is_empty() {
-            target_list = serde_json::from_str(&read).unwrap();
+        // 左部Fromテーブル、送信者を開いている間はメッセージ一覧
+        if self.opened.is_some() {
+            self.draw_message_list(f, horizon_chunk[0]);
+        } else {
+            self.draw_from_table(f, horizon_chunk[0]);
         }
 
+        let target_list = self.store.rules()
+            .iter()
+            .map(|rule| rule.to_string())
+            .collect::<Vec<String>>();
+        let selected = clamp_index(self.list_state.target.selected(), target_list.len());
+        self.list_state.target.select(selected);
+
         // FIXME: clientを分離しよう！
-        // FIXME: 都度ファイル読み書きじゃなく、良いタイミングせ書き込もう！
         // FIXME: エラーハンドリングしよう！ anyhow::Error?
 
         // 右部Targetリスト
         let right = render_list_items(
             "Target",
-            target_list.iter().map(AsRef::as_ref).collect()
+            target_list.iter().map(AsRef::as_ref).collect(),
+            self.focus == Focus::Target,
         );
-        f.render_widget(right, horizon_chunk[2]);
+        f.render_stateful_widget(right, horizon_chunk[1], &mut self.list_state.target);
     }
 
-    pub fn event(&mut self, key: KeyCode) -> Result<EventState, ()> {
-        match key {
-            KeyCode::Char('q') => {
-                return Ok(EventState::NotConsumed);
-            },
-            KeyCode::Char('a') => {
-                if let Some(selected) = self.list_state.from.selected() {
-                    let mark_list = fs::read_to_string(self.config.mark_list_path).unwrap();
-                    let mut add_list: Vec<String> = Vec::new();
+    // 選択中の送信者の未読を新しい順に出す
+    fn draw_preview<B: Backend>(&self, f: &mut Frame<'_, B>, area: Rect) {
+        let message_list = self.selected_messages();
 
-                    // リストが空じゃなかったらパースして突っ込む
-                    if !&mark_list.is_empty() {
-                        let parsed: Vec::<String> = serde_json::from_str(&mark_list).unwrap();
-                        add_list.append(&mut parsed.clone());
-                    }
+        let items = message_list.iter().map(|(m, received_at)| {
+            let date = received_at
+                .map(|d| d.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| "-".to_string());
+            ListItem::new(Spans::from(vec![
+                Span::styled(date, Style::default().fg(Color::DarkGray)),
+                Span::raw("  "),
+                Span::styled(
+                    m.subject().unwrap_or_else(|| "(no subject)".to_string()),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw("  "),
+                Span::styled(m.snippet_text().unwrap_or_default(), Style::default().fg(Color::DarkGray)),
+            ]))
+        }).collect::<Vec<ListItem>>();
 
-                    add_list.push(self.address_list[selected].to_string());
+        let title = match self.selected_from_row() {
+            Some(row) => format!("Preview: {} ({})", row.label().trim(), message_list.len()),
+            None => "Preview".to_string(),
+        };
+        let list = List::new(items).block(pane_block(&title, false));
+        f.render_widget(list, area);
+    }
 
-                    // 重複排除
-                    add_list.sort();
-                    add_list.dedup();
+    // 開いた送信者のメッセージをチェックボックス付きで並べる
+    fn draw_message_list<B: Backend>(&mut self, f: &mut Frame<'_, B>, area: Rect) {
+        let row = match &self.opened {
+            Some(row) => row,
+            None => return,
+        };
+        let message_list = self.row_messages(row);
 
-                    fs::write(self.config.mark_list_path, &serde_json::to_vec(&add_list).unwrap()).unwrap();
+        let items = message_list.iter().map(|(m, received_at)| {
+            let checked = m.id.as_ref().is_some_and(|id| self.selected_ids.contains(id));
+            let date = received_at
+                .map(|d| d.with_timezone(&Local).format("%Y-%m-%d").to_string())
+                .unwrap_or_else(|| "-".to_string());
+            ListItem::new(Spans::from(vec![
+                Span::raw(if checked { "[x] " } else { "[ ] " }),
+                Span::styled(date, Style::default().fg(Color::DarkGray)),
+                Span::raw("  "),
+                Span::raw(m.subject().unwrap_or_else(|| "(no subject)".to_string())),
+            ]))
+        }).collect::<Vec<ListItem>>();
 
-                    // FIXME: ファイル読み書きを関数化（とログ関数）
-                    // FIXME: 逆順にしないと見切れる
-                    // FIXME: スクロールとかできんのかね
+        let selected_count = message_list.iter()
+            .filter(|(m, _)| m.id.as_ref().is_some_and(|id| self.selected_ids.contains(id)))
+            .count();
+        let title = format!("Messages: {} ({}/{} selected)", row.label().trim(), selected_count, message_list.len());
 
-                    let read = fs::read_to_string(self.config.log_path).unwrap();
-                    let mut log_list: Vec<String> = Vec::new();
-                    if !read.is_empty() {
-                        log_list = serde_json::from_str(&read).unwrap();
-                    }
+        let selected = clamp_index(self.list_state.messages.selected(), items.len());
+        self.list_state.messages.select(selected);
+
+        let list = List::new(items)
+            .block(pane_block(&title, self.focus == Focus::Messages))
+            .highlight_style(highlight_style());
+        f.render_stateful_widget(list, area, &mut self.list_state.messages);
+    }
+
+    // 選択中の行の送信者（ドメインの行ならそのドメインの送信者すべて）の未読
+    fn selected_messages(&self) -> Vec<(&Message, Option<DateTime<Utc>>)> {
+        match self.selected_from_row() {
+            Some(row) => self.row_messages(row),
+            None => Vec::new(),
+        }
+    }
+
+    // 行の送信者の未読を受信日時と一緒に新しい順に
+    fn row_messages(&self, row: &FromRow) -> Vec<(&Message, Option<DateTime<Utc>>)> {
+        let found = self.row_cache.get(&row.id());
+        let indexes = match found {
+            Some(indexes) => indexes.clone(),
+            None => self.find_row_messages(row),
+        };
+
+        indexes.into_iter()
+            .map(|(i, received_at)| (&self.message_list[i], received_at))
+            .collect()
+    }
+
+    // 開いた行と選択中の行の未読を取っておく
+    fn cache_row_messages(&mut self) {
+        let rows = self.opened.iter()
+            .chain(self.selected_from_row())
+            .map(|row| (row.id(), row.clone()))
+            .collect::<Vec<(String, FromRow)>>();
+        self.row_cache.retain(|id, _| rows.iter().any(|(row_id, _)| row_id == id));
+
+        for (id, row) in rows {
+            if !self.row_cache.contains_key(&id) {
+                let indexes = self.find_row_messages(&row);
+                self.row_cache.insert(id, indexes);
+            }
+        }
+    }
+
+    fn find_row_messages(&self, row: &FromRow) -> RowIndexes {
+        let mut indexes = self.message_list.iter()
+            .enumerate()
+            .filter(|(_, m)| match (m.sender(), row) {
+                (Some(s), FromRow::Group { key, .. }) => self.grouping.key(&s.address) == *key,
+                (Some(s), FromRow::Sender { sender, .. }) => s.address == sender.address,
+                (None, _) => false,
+            })
+            .map(|(i, m)| (i, m.received_at()))
+            .collect::<Vec<(usize, Option<DateTime<Utc>>)>>();
+        // 日付は比較のたびに読み直さないよう先に読んでおく
+        indexes.sort_by_key(|(_, received_at)| Reverse(*received_at));
 
-                    log_list.push(format!("[{}] {}: {}", Local::now().format("%Y年%m月%d日 %H:%M:%S"), "Add", self.address_list[selected]));
+        indexes
+    }
+
+    // 送信者・未読数・最新の日付・Target一致の印を並べたテーブル
+    // 行数が多くても見えている範囲の行だけ作る
+    fn draw_from_table<B: Backend>(&mut self, f: &mut Frame<'_, B>, area: Rect) {
+        // 枠の上下とヘッダーの分を除いた行数
+        let height = area.height.saturating_sub(3) as usize;
+        self.list_state.from_height = height;
+
+        let offset = visible_offset(self.list_state.from_offset, self.list_state.from.selected(), height);
+        self.list_state.from_offset = offset;
+
+        // 未読数が変わった送信者とそのグループは色を変える
+        let changed_keys = self.changed_senders.iter()
+            .map(|address| self.grouping.key(address))
+            .collect::<HashSet<String>>();
+
+        let end = (offset + height).min(self.from_rows.len());
+        let rows = self.from_rows[offset.min(end)..end].iter().map(|row| {
+            let changed = match row {
+                FromRow::Group { key, .. } => changed_keys.contains(key),
+                FromRow::Sender { sender, .. } => self.changed_senders.contains(&sender.address),
+            };
+            let newest = row.newest()
+                .map(|d| d.with_timezone(&Local).format("%Y-%m-%d").to_string())
+                .unwrap_or_else(|| "-".to_string());
+            Row::new(vec![
+                Cell::from(row.label()),
+                Cell::from(format!("{:>5}", row.count())),
+                Cell::from(newest),
+                Cell::from(if row.targeted() { "  *" } else { "" }),
+            ])
+                .style(if changed { Style::default().fg(Color::Green) } else { Style::default() })
+        });
+
+        // 未読数の列は最大の桁数に合わせる
+        let count_width = self.from_rows.iter()
+            .map(|r| r.count().to_string().len())
+            .max()
+            .unwrap_or(0)
+            .max(5) as u16;
+
+        // 絞り込み中はタイトルに入力を出す
+        let from_title = match (self.searching, self.filter.is_empty()) {
+            (true, _) => format!("From /{}_", self.filter),
+            (false, false) => format!("From /{}", self.filter),
+            (false, true) => "From".to_string(),
+        };
+        let from_title = match self.refreshing {
+            true => format!("{} (refreshing)", from_title),
+            false => from_title,
+        };
+
+        let header = Row::new(vec!["Sender", "Count", "Newest", "Target"])
+            .style(Style::default().add_modifier(Modifier::BOLD));
+        // 送信者の列は残りの幅をすべて使う（枠と列の間隔を除く）
+        let sender_width = area.width.saturating_sub(2 + count_width + 10 + 6 + 3);
+        let widths = [
+            Constraint::Length(sender_width),
+            Constraint::Length(count_width),
+            Constraint::Length(10),
+            Constraint::Length(6),
+        ];
+        let table = Table::new(rows)
+            .header(header)
+            .block(pane_block(&from_title, self.focus == Focus::From))
+            .widths(&widths)
+            .column_spacing(1)
+            .highlight_style(highlight_style());
+
+        // 表示している範囲の中での選択位置
+        let mut state = TableState::default();
+        state.select(self.list_state.from.selected().map(|s| s - offset));
+        f.render_stateful_widget(table, area, &mut state);
+    }
+
+    // Fromペインの選択を移動する
+    // 1行ずつの移動は端で反対側に回る
+    fn move_from_selection(&mut self, key: KeyCode) {
+        let len = self.from_rows.len();
+        let selected = match self.list_state.from.selected() {
+            Some(selected) if len > 0 => selected,
+            _ => return,
+        };
+        let page = self.list_state.from_height.max(1);
+
+        let selected = match key {
+            KeyCode::Down if selected + 1 >= len => 0,
+            KeyCode::Down => selected + 1,
+            KeyCode::Up if selected == 0 => len - 1,
+            KeyCode::Up => selected - 1,
+            KeyCode::PageDown => (selected + page).min(len - 1),
+            KeyCode::PageUp => selected.saturating_sub(page),
+            KeyCode::Home => 0,
+            KeyCode::End => len - 1,
+            _ => selected,
+        };
+        self.list_state.from.select(Some(selected));
+    }
+
+    pub fn event(&mut self, key: KeyCode) -> Result<EventState> {
+        // 実行中は終了（実行中のチャンクを待ってから抜ける）のほかはキー入力を受け付けない
+        if self.is_executing() {
+            if key == KeyCode::Char('q') {
+                return Ok(EventState::NotConsumed);
+            }
+            return Ok(EventState::Consumed);
+        }
 
-                    fs::write(self.config.log_path, &serde_json::to_vec(&log_list).unwrap()).unwrap();
+        if self.searching {
+            return Ok(self.search_event(key));
+        }
+
+        match key {
+            KeyCode::Char('q') => {
+                return Ok(EventState::NotConsumed);
+            },
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::From | Focus::Messages => Focus::Target,
+                    Focus::Target if self.opened.is_some() => Focus::Messages,
+                    Focus::Target => Focus::From,
+                };
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Enter if self.focus == Focus::From => {
+                // 送信者のメッセージを1通ずつ選べるようにする
+                if let Some(row) = self.selected_from_row() {
+                    self.opened = Some(row.clone());
+                    self.list_state.messages.select(Some(0));
+                    self.focus = Focus::Messages;
                 }
                 return Ok(EventState::Consumed);
             },
-            KeyCode::Char('d') => {
-                // 消せるようにしたいなー
+            KeyCode::Esc | KeyCode::Left if self.focus == Focus::Messages => {
+                // 選んだメッセージはそのままで送信者の一覧に戻る
+                self.opened = None;
+                self.focus = Focus::From;
                 return Ok(EventState::Consumed);
             },
-            KeyCode::Char('e') => {
-                let mut id_list: Vec<&str> = Vec::new();
-
-                let content = fs::read_to_string(self.config.mark_list_path).unwrap();
-                let mark_list: Vec<String> = serde_json::from_str(&content).unwrap();
-
-                for from_value in mark_list {
-                    let match_message_list = self.message_list.into_iter().filter(
-                        |m| m.payload.as_ref().unwrap().headers.as_ref().unwrap().contains(
-                            &Header {
-                                name: Some("From".to_string()),
-                                value: Some(from_value.clone()),
-                            }
-                        )
-                    ).collect::<Vec<&Message>>();
-
-                    for message in match_message_list {
-                        id_list.push(message.id.as_ref().unwrap());
+            KeyCode::Char(' ') if self.focus == Focus::Messages => {
+                let id = match (&self.opened, self.list_state.messages.selected()) {
+                    (Some(row), Some(selected)) => self.row_messages(row)
+                        .get(selected)
+                        .and_then(|(m, _)| m.id.clone()),
+                    _ => None,
+                };
+                if let Some(id) = id {
+                    if !self.selected_ids.remove(&id) {
+                        self.selected_ids.insert(id);
                     }
+                    self.update_targets();
+                }
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Down | KeyCode::Up if self.focus == Focus::Messages => {
+                let len = match &self.opened {
+                    Some(row) => self.row_messages(row).len(),
+                    None => 0,
+                };
+                if let Some(selected) = self.list_state.messages.selected() {
+                    let selected = match key {
+                        KeyCode::Down if selected + 1 >= len => 0,
+                        KeyCode::Down => selected + 1,
+                        _ if selected == 0 => len.saturating_sub(1),
+                        _ => selected - 1,
+                    };
+                    self.list_state.messages.select(Some(selected));
+                }
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Char('a') => {
+                if self.focus != Focus::From {
+                    return Ok(EventState::Consumed);
                 }
+                if let Some(selected) = self.list_state.from.selected() {
+                    // ドメインの行ならドメイン全体、送信者の行ならアドレスのルール
+                    let rule = self.from_rows[selected].rule(self.grouping);
+                    self.store.add_rule(rule.clone())?;
+                    self.update_targets();
 
-                println!("{:?}", id_list);
+                    // FIXME: 逆順にしないと見切れる
+                    // FIXME: スクロールとかできんのかね
 
-                println!("mark as read ... complete");
+                    self.store.push_log("Add", &rule.to_string())?;
+                }
                 return Ok(EventState::Consumed);
             },
-            KeyCode::Down => {
-                if let Some(selected) = self.list_state.from.selected() {
-                    if selected >= self.address_list.len() - 1 {
-                        self.list_state.from.select(Some(0));
-                    } else {
-                        self.list_state.from.select(Some(selected + 1));
-                    }
+            KeyCode::Char('d') => {
+                if self.focus != Focus::Target {
+                    return Ok(EventState::Consumed);
                 }
-                if let Some(selected) = self.list_state.count.selected() {
-                    if selected >= self.count_list.len() - 1 {
-                        self.list_state.count.select(Some(0));
-                    } else {
-                        self.list_state.count.select(Some(selected + 1));
+                if let Some(selected) = self.list_state.target.selected() {
+                    let deleted = match self.store.remove_rule(selected)? {
+                        Some(deleted) => deleted,
+                        None => return Ok(EventState::Consumed),
+                    };
+                    self.list_state.target.select(clamp_index(self.list_state.target.selected(), self.store.rules().len()));
+                    self.update_targets();
+
+                    self.store.push_log("Delete", &deleted.to_string())?;
+                }
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Char('g') => {
+                self.grouping = self.grouping.next();
+                self.expanded.clear();
+                self.list_state.from.select(Some(0));
+                self.update_lists();
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Right if self.focus == Focus::From => {
+                // ドメインの行を展開して送信者を表示
+                if let Some(FromRow::Group { key, .. }) = self.selected_from_row() {
+                    self.expanded.insert(key.clone());
+                    self.update_lists();
+                }
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Left if self.focus == Focus::From => {
+                // 展開中のドメインを閉じ、選択をドメインの行に戻す
+                let selected = match self.list_state.from.selected() {
+                    Some(selected) => selected,
+                    None => return Ok(EventState::Consumed),
+                };
+                let group_index = self.from_rows[..=selected].iter()
+                    .rposition(|r| matches!(r, FromRow::Group { .. }));
+                if let Some(i) = group_index {
+                    if let FromRow::Group { key, .. } = &self.from_rows[i] {
+                        self.expanded.remove(key);
                     }
+                    self.list_state.from.select(Some(i));
+                    self.update_lists();
                 }
                 return Ok(EventState::Consumed);
             },
-            KeyCode::Up => {
-                if let Some(selected) = self.list_state.from.selected() {
-                    if selected > 0 {
-                        self.list_state.from.select(Some(selected - 1));
+            KeyCode::Char('s') => {
+                self.sort_order = self.sort_order.next();
+                self.update_lists();
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Char('/') => {
+                self.searching = true;
+                self.opened = None;
+                self.focus = Focus::From;
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Esc if !self.filter.is_empty() => {
+                self.filter.clear();
+                self.update_lists();
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Char('r') => {
+                self.start_refresh();
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Char('p') => {
+                self.preview = !self.preview;
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Char('y') => {
+                self.dry_run = !self.dry_run;
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Char('e') => {
+                let targets = self.execute_targets()?;
+
+                if self.dry_run {
+                    self.write_dry_run_report(&targets)?;
+                    return Ok(EventState::Consumed);
+                }
+
+                let id_list = targets.iter()
+                    .flat_map(|(_, ids)| ids.iter().cloned())
+                    .collect::<Vec<String>>();
+
+                self.start_execution(LabelChange::MarkRead, targets, None, id_list)?;
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Char('u') => {
+                // 最後のExecuteで既読にしたメッセージを未読に戻す
+                let index = find_undo_entry(self.store.journal(), None)?;
+                let ids = self.store.journal()[index].ids.clone();
+
+                self.start_execution(LabelChange::MarkUnread, Vec::new(), Some(index), ids)?;
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Down if self.focus == Focus::Target => {
+                let len = self.store.rules().len();
+                if let Some(selected) = self.list_state.target.selected() {
+                    if selected + 1 >= len {
+                        self.list_state.target.select(Some(0));
                     } else {
-                        self.list_state.from.select(Some(self.address_list.len() - 1));
+                        self.list_state.target.select(Some(selected + 1));
                     }
                 }
-                if let Some(selected) = self.list_state.count.selected() {
+                return Ok(EventState::Consumed);
+            },
+            KeyCode::Up if self.focus == Focus::Target => {
+                let len = self.store.rules().len();
+                if let Some(selected) = self.list_state.target.selected() {
                     if selected > 0 {
-                        self.list_state.count.select(Some(selected - 1));
+                        self.list_state.target.select(Some(selected - 1));
                     } else {
-                        self.list_state.count.select(Some(self.count_list.len() - 1));
+                        self.list_state.target.select(Some(len.saturating_sub(1)));
                     }
                 }
                 return Ok(EventState::Consumed);
             },
+            KeyCode::Down | KeyCode::Up | KeyCode::PageDown | KeyCode::PageUp | KeyCode::Home | KeyCode::End => {
+                self.move_from_selection(key);
+                return Ok(EventState::Consumed);
+            },
             _ => {
                 return Ok(EventState::Consumed);
             }
         }
     }
+
+    // エラーをLogsペインに出す
+    pub fn report_error(&mut self, err: &Error) {
+        if let Err(log_err) = self.store.push_log("Error", &err.to_string()) {
+            self.unlogged_error = Some(log_err.to_string());
+        }
+    }
+
+    // 絞り込み文字列の入力
+    // 1文字ごとにリストを絞り込む
+    fn search_event(&mut self, key: KeyCode) -> EventState {
+        match key {
+            KeyCode::Char(c) => self.filter.push(c),
+            KeyCode::Backspace => {
+                self.filter.pop();
+            },
+            // 絞り込みを残して入力を終える
+            KeyCode::Enter => self.searching = false,
+            KeyCode::Esc => {
+                self.searching = false;
+                self.filter.clear();
+            },
+            _ => return EventState::Consumed,
+        }
+        self.update_lists();
+
+        EventState::Consumed
+    }
+
+    fn selected_from_row(&self) -> Option<&FromRow> {
+        self.list_state.from.selected().and_then(|i| self.from_rows.get(i))
+    }
+
+    // 既読化の代わりに対象メッセージのレポートを書き出す
+    fn write_dry_run_report(&mut self, targets: &Vec<(String, Vec<String>)>) -> Result<()> {
+        let report = DryRunReport::new(&self.message_list, targets);
+        report.write(self.config.report_path)?;
+
+        for (target, count) in &report.per_target {
+            self.store.push_log("Dry-run", &format!("{} ({} messages)", target, count))?;
+        }
+        self.store.push_log(
+            "Dry-run",
+            &format!("total {} messages (report: {})", report.total, self.config.report_path),
+        )?;
+
+        Ok(())
+    }
+
+    fn start_execution(
+        &mut self,
+        change: LabelChange,
+        targets: Vec<(String, Vec<String>)>,
+        journal_index: Option<usize>,
+        id_list: Vec<String>,
+    ) -> Result<()> {
+        if id_list.is_empty() {
+            return self.store.push_log(change.name(), "no target messages");
+        }
+
+        self.store.push_log(
+            change.name(),
+            &format!("start {} messages", id_list.len()),
+        )?;
+
+        let chunks = id_list
+            .chunks(BATCH_MODIFY_LIMIT)
+            .map(|c| c.to_vec())
+            .collect::<VecDeque<Vec<String>>>();
+
+        self.execution = Some(Execution {
+            change,
+            targets,
+            journal_index,
+            chunk_count: chunks.len(),
+            chunks,
+            processed: 0,
+            succeeded: Vec::new(),
+            failed: 0,
+        });
+        self.spawn_next_chunk();
+
+        Ok(())
+    }
+
+    // 表示している未読リストを裏で同期する
+    // 同期中なら何もしない
+    pub fn start_refresh(&mut self) {
+        if self.refreshing {
+            return;
+        }
+        self.refreshing = true;
+        self.last_refresh = Instant::now();
+        self.refresh_base = self.message_list.iter()
+            .filter_map(|m| Some((m.id.clone()?, m.sender()?.address)))
+            .collect();
+
+        let tasks = self.tasks.clone();
+        let backend = self.backend.clone();
+        let cache = MetadataCache::new(&self.message_list, self.history_id.clone());
+        let max_results = self.config.max_results;
+        let unread_limit = self.config.unread_limit;
+        let concurrency = self.config.concurrency;
+
+        tokio::spawn(async move {
+            let on_page = |page: &[Message], _| {
+                let _ = tasks.send(TaskResult::RefreshPage(page.to_vec()));
+            };
+            let result = backend::sync_unread(backend.as_ref(), max_results, unread_limit, concurrency, &cache, on_page).await;
+            let _ = tasks.send(TaskResult::Refresh(result));
+        });
+    }
+
+    // 設定した間隔ごとに同期する
+    pub fn tick(&mut self) {
+        let interval = self.config.refresh_interval;
+        if interval == 0 || self.is_executing() {
+            return;
+        }
+        if self.last_refresh.elapsed() >= Duration::from_secs(interval) {
+            self.start_refresh();
+        }
+    }
+
+    // 裏で動かしたAPI呼び出しの結果を反映する
+    pub fn handle_task(&mut self, result: TaskResult) -> Result<()> {
+        match result {
+            TaskResult::RefreshPage(page) => {
+                self.add_refreshed_page(page);
+                Ok(())
+            },
+            TaskResult::Refresh(result) => self.finish_refresh(result),
+            TaskResult::Chunk(chunk, result) => self.finish_chunk(chunk, result),
+            TaskResult::Fetched(result) => {
+                let mut fetched = result?;
+                self.message_list.append(&mut fetched.messages);
+                self.update_targets();
+                // 取得できなかったものは次の同期で履歴から加わる
+                if fetched.failed.is_empty() {
+                    return Ok(());
+                }
+                self.store.push_log("Undo", &backend::describe_failed(&fetched.failed))
+            },
+        }
+    }
+
+    // 同期した未読リストに差し替える
+    // 全件取得中は届いたページのうちまだ表示していないものを加える
+    // 消えたものや並びは同期の完了時にまとめて反映する
+    fn add_refreshed_page(&mut self, page: Vec<Message>) {
+        let listed = self.message_list.iter()
+            .filter_map(|m| m.id.clone())
+            .collect::<HashSet<String>>();
+        let mut added = page.into_iter()
+            .filter(|m| m.id.as_ref().is_some_and(|id| !listed.contains(id) && !self.read_ids.contains(id)))
+            .collect::<Vec<Message>>();
+        if added.is_empty() {
+            return;
+        }
+
+        self.message_list.append(&mut added);
+        self.update_targets();
+    }
+
+    fn finish_refresh(&mut self, result: Result<Synced>) -> Result<()> {
+        self.refreshing = false;
+
+        let Synced { cache: synced, failed } = result?;
+        if !failed.is_empty() {
+            self.store.push_log("Refresh", &backend::describe_failed(&failed))?;
+        }
+        let mut message_list = synced.messages();
+        message_list.retain(|m| m.id.as_ref().is_none_or(|id| !self.read_ids.contains(id)));
+
+        // 同期中に既読にしたものは変化に数えない
+        let mut before = HashMap::new();
+        for (id, address) in mem::take(&mut self.refresh_base) {
+            if !self.read_ids.contains(&id) {
+                *before.entry(address).or_insert(0) += 1;
+            }
+        }
+        let after = sender_counts(&message_list);
+        self.changed_senders = after.iter()
+            .filter(|(address, count)| before.get(*address) != Some(count))
+            .map(|(address, _)| address.clone())
+            .collect();
+
+        self.message_list = message_list;
+        self.history_id = synced.history_id().map(|id| id.to_string());
+        self.update_targets();
+        self.save_cache()?;
+
+        // 変化がなければログは出さない
+        if self.changed_senders.is_empty() {
+            return Ok(());
+        }
+        self.store.push_log(
+            "Refresh",
+            &format!("{} unread messages ({} senders changed)", self.message_list.len(), self.changed_senders.len()),
+        )
+    }
+
+    pub fn save_cache(&self) -> Result<()> {
+        MetadataCache::new(&self.message_list, self.history_id.clone()).save(self.config.cache_path)
+    }
+
+    // 表示中の未読リスト
+    pub fn messages(&self) -> &Vec<Message> {
+        &self.message_list
+    }
+
+    pub fn store(&self) -> &Store<'a> {
+        &self.store
+    }
+
+    // 前回の同期で未読数が変わった送信者
+    pub fn changed_senders(&self) -> &HashSet<String> {
+        &self.changed_senders
+    }
+
+    pub fn is_executing(&self) -> bool {
+        self.execution.is_some()
+    }
+
+    // 終了するときは残りのチャンクを取りやめ、実行中のチャンクの結果だけ待つ
+    pub fn interrupt_execution(&mut self) {
+        let execution = match self.execution.as_mut() {
+            Some(execution) => execution,
+            None => return,
+        };
+        if execution.chunks.is_empty() {
+            return;
+        }
+
+        let skipped = execution.chunks.iter().map(|c| c.len()).sum::<usize>();
+        execution.chunks.clear();
+        let name = execution.change.name();
+        if let Err(err) = self.store.push_log(name, &format!("interrupted, {} messages skipped", skipped)) {
+            self.unlogged_error = Some(err.to_string());
+        }
+    }
+
+    // 次のチャンクのラベル変更を裏で実行する
+    fn spawn_next_chunk(&mut self) {
+        let execution = match self.execution.as_mut() {
+            Some(execution) => execution,
+            None => return,
+        };
+        let chunk = match execution.chunks.pop_front() {
+            Some(chunk) => chunk,
+            None => return,
+        };
+
+        let change = execution.change;
+        let backend = self.backend.clone();
+        let tasks = self.tasks.clone();
+        tokio::spawn(async move {
+            let result = {
+                let ids = chunk.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+                change.apply(backend.as_ref(), &ids).await
+            };
+            let _ = tasks.send(TaskResult::Chunk(chunk, result));
+        });
+    }
+
+    // 1チャンク分の結果を記録して次のチャンクに進む
+    // 履歴やログに書き込めなくても実行は止めず、最初の書き込みエラーを最後に返す
+    fn finish_chunk(&mut self, chunk: Vec<String>, result: Result<()>) -> Result<()> {
+        let execution = match self.execution.as_mut() {
+            Some(execution) => execution,
+            None => return Ok(()),
+        };
+        execution.processed += 1;
+
+        let mut written = Ok(());
+        let log = match result {
+            Ok(_) => {
+                let len = chunk.len();
+                // 途中で終了しても取り消せるようチャンクごとに記録する
+                if execution.change == LabelChange::MarkRead {
+                    written = self.store.journal_chunk(execution.journal_index, &execution.targets, &chunk)
+                        .map(|index| execution.journal_index = index);
+                }
+                execution.succeeded.extend(chunk);
+                format!("chunk {}/{} ok ({} messages)", execution.processed, execution.chunk_count, len)
+            },
+            Err(err) => {
+                execution.failed += 1;
+                format!("chunk {}/{} failed: {}", execution.processed, execution.chunk_count, err)
+            }
+        };
+        let written = written.and(self.store.push_log(execution.change.name(), &log));
+
+        if execution.chunks.is_empty() {
+            let finished = self.finish_execution();
+            return written.and(finished);
+        }
+        self.spawn_next_chunk();
+
+        written
+    }
+
+    fn finish_execution(&mut self) -> Result<()> {
+        let execution = match self.execution.take() {
+            Some(execution) => execution,
+            None => return Ok(()),
+        };
+
+        let finished = match execution.change {
+            LabelChange::MarkRead => self.finish_mark_read(&execution),
+            LabelChange::MarkUnread => self.finish_mark_unread(&execution),
+        };
+
+        let logged = self.store.push_log(
+            execution.change.name(),
+            &format!("complete {} messages", execution.succeeded.len()),
+        );
+
+        finished.and(logged)
+    }
+
+    // 既読化できたメッセージと送信者を各リストから取り除く
+    // ドメインや件名などの条件のルールは次の未読にも使うので残しておく
+    fn finish_mark_read(&mut self, execution: &Execution) -> Result<()> {
+        self.message_list.retain(
+            |m| m.id.as_ref().is_none_or(|id| !execution.succeeded.contains(id))
+        );
+        for id in &execution.succeeded {
+            self.selected_ids.remove(id);
+            self.read_ids.insert(id.clone());
+        }
+
+        // 全メッセージを既読化できたアドレスだけTargetから外す
+        let done_list = execution.targets.iter()
+            .filter(|(_, ids)| !ids.is_empty() && ids.iter().all(|id| self.read_ids.contains(id)))
+            .map(|(rule, _)| rule.as_str())
+            .collect::<HashSet<&str>>();
+        let removed = self.store.remove_rules_where(
+            |rule| rule.is_address() && done_list.contains(rule.to_string().as_str())
+        );
+        self.list_state.target.select(clamp_index(self.list_state.target.selected(), self.store.rules().len()));
+
+        self.update_targets();
+
+        for rule in removed? {
+            self.store.push_log("Delete", &rule.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    // 未読に戻したメッセージを裏で取得し直す
+    fn finish_mark_unread(&mut self, execution: &Execution) -> Result<()> {
+        let ids = execution.succeeded.iter()
+            .filter(|id| !self.message_list.iter().any(|m| m.id.as_ref() == Some(id)))
+            .cloned()
+            .collect::<Vec<String>>();
+        for id in &execution.succeeded {
+            self.read_ids.remove(id);
+        }
+
+        let backend = self.backend.clone();
+        let tasks = self.tasks.clone();
+        let concurrency = self.config.concurrency;
+        tokio::spawn(async move {
+            let result = backend::fetch_metadata(backend.as_ref(), &ids, concurrency).await;
+            let _ = tasks.send(TaskResult::Fetched(result));
+        });
+
+        // 一部失敗したら再実行できるよう残しておく
+        match execution.journal_index {
+            Some(index) if execution.failed == 0 => self.store.set_undone(index),
+            _ => Ok(()),
+        }
+    }
 }
 
 pub struct ListStates {
-    from: ListState,
-    count: ListState,
+    from: TableState,
+    // Fromテーブルの表示開始位置と表示できる行数
+    from_offset: usize,
+    from_height: usize,
+    target: ListState,
+    messages: ListState,
 }
 
 impl ListStates {
-    pub fn new(from: ListState, count: ListState) -> Self {
+    pub fn new(from: TableState, target: ListState) -> Self {
         Self {
             from,
-            count,
+            from_offset: 0,
+            from_height: 0,
+            target,
+            messages: ListState::default(),
         }
     }
 }
 
-fn render_list_items<'a>(block_name: &'a str, list_items: Vec<&'a str>) -> List<'a> {
-    let from_block = Block::default()
+// アドレスごとの未読数
+fn sender_counts(message_list: &Vec<Message>) -> HashMap<String, usize> {
+    message::get_sender_count_list(message_list)
+        .into_iter()
+        .map(|s| (s.sender.address, s.count))
+        .collect()
+}
+
+// 選択位置をリストの長さに収める
+fn clamp_index(selected: Option<usize>, len: usize) -> Option<usize> {
+    match len {
+        0 => None,
+        len => Some(selected.unwrap_or(0).min(len - 1)),
+    }
+}
+
+// 選択行が見えるように表示開始位置をずらす
+fn visible_offset(offset: usize, selected: Option<usize>, height: usize) -> usize {
+    match selected {
+        Some(selected) if selected < offset => selected,
+        Some(selected) if height > 0 && selected >= offset + height => selected + 1 - height,
+        _ => offset,
+    }
+}
+
+// フォーカス中のペインは枠を強調
+fn pane_block(title: &str, focused: bool) -> Block<'_> {
+    let border_color = if focused { Color::Yellow } else { Color::White };
+
+    Block::default()
         .borders(Borders::ALL)
         .style(Style::default().fg(Color::White))
-        .title(block_name)
-        .border_type(BorderType::Plain);
+        .border_style(Style::default().fg(border_color))
+        .title(title)
+        .border_type(BorderType::Plain)
+}
+
+fn highlight_style() -> Style {
+    Style::default()
+        .bg(Color::Yellow)
+        .fg(Color::Black)
+        .add_modifier(Modifier::BOLD)
+}
+
+fn render_list_items<'a>(block_name: &'a str, list_items: Vec<&'a str>, focused: bool) -> List<'a> {
+    let from_block = pane_block(block_name, focused);
 
     let items: Vec<_> = list_items
         .iter()
@@ -293,13 +1260,7 @@ fn render_list_items<'a>(block_name: &'a str, list_items: Vec<&'a str>) -> List<
         })
         .collect();
 
-    let list = List::new(items).block(from_block).highlight_style(
-        Style::default()
-            .bg(Color::Yellow)
-            .fg(Color::Black)
-            .add_modifier(Modifier::BOLD),
-    );
+    let list = List::new(items).block(from_block).highlight_style(highlight_style());
 
     list
 }
-
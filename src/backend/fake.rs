@@ -0,0 +1,206 @@
+use super::MailBackend;
+use crate::error::{Error, Result};
+use crate::message::{Header, History, HistoryMessage, HistoryPage, Message, MessageListPage, Payload};
+use chrono::Utc;
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use std::{
+    fs,
+    sync::Mutex,
+};
+
+// シナリオファイルの1メッセージ分
+#[derive(Deserialize, Debug)]
+pub struct FakeMessage {
+    pub id: String,
+    pub from: String,
+    #[serde(default)]
+    pub subject: Option<String>,
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub snippet: Option<String>,
+    #[serde(default = "default_labels")]
+    pub labels: Vec<String>,
+}
+
+fn default_labels() -> Vec<String> {
+    vec!["INBOX".to_string(), "UNREAD".to_string()]
+}
+
+// メモリ上のメールボックスで動く実装
+// Googleアカウントなしで画面や既読化の流れを確認するためのもの
+pub struct FakeBackend {
+    messages: Mutex<Vec<Message>>,
+    // 起動してからの変更履歴
+    history: Mutex<Vec<History>>,
+    // 起動時の履歴ID
+    // 前回の起動時の履歴IDは残っていない扱いにするため起動時刻から作る
+    base_history_id: u64,
+}
+
+impl FakeBackend {
+    pub fn new(messages: Vec<FakeMessage>) -> Self {
+        let messages = messages.into_iter().map(|m| {
+            let mut headers = vec![header("From", m.from)];
+            if let Some(subject) = m.subject {
+                headers.push(header("Subject", subject));
+            }
+            if let Some(date) = m.date {
+                headers.push(header("Date", date));
+            }
+
+            Message {
+                id: Some(m.id.clone()),
+                thread_id: Some(m.id),
+                label_ids: Some(m.labels),
+                history_id: None,
+                internal_date: None,
+                snippet: m.snippet,
+                payload: Some(Payload {
+                    headers: Some(headers),
+                }),
+            }
+        }).collect();
+
+        Self {
+            messages: Mutex::new(messages),
+            history: Mutex::new(Vec::new()),
+            base_history_id: Utc::now().timestamp_millis() as u64,
+        }
+    }
+
+    // [{"id": "1", "from": "Foo <foo@example.com>"}, ...] 形式のファイルを読み込む
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|source| Error::File { path: path.to_string(), source })?;
+        let messages: Vec<FakeMessage> = serde_json::from_str(&content)
+            .map_err(|source| Error::ParseFile { path: path.to_string(), source })?;
+
+        Ok(Self::new(messages))
+    }
+}
+
+fn header(name: &str, value: String) -> Header {
+    Header {
+        name: Some(name.to_string()),
+        value: Some(value),
+    }
+}
+
+impl MailBackend for FakeBackend {
+    fn list_unread<'a>(
+        &'a self,
+        page_token: Option<&'a str>,
+        max_results: u32,
+    ) -> BoxFuture<'a, Result<MessageListPage>> {
+        Box::pin(async move {
+            let messages = self.messages.lock().unwrap();
+            let unread = messages.iter().filter(|m| m.is_listed_unread()).collect::<Vec<&Message>>();
+
+            // ページトークンは次ページの開始位置
+            let start = page_token.and_then(|t| t.parse().ok()).unwrap_or(0);
+            let end = unread.len().min(start + max_results as usize);
+
+            let page = unread[start.min(end)..end].iter().map(|m| Message {
+                id: m.id.clone(),
+                thread_id: m.thread_id.clone(),
+                label_ids: None,
+                history_id: None,
+                internal_date: None,
+                snippet: None,
+                payload: None,
+            }).collect::<Vec<Message>>();
+
+            Ok(MessageListPage {
+                messages: if page.is_empty() { None } else { Some(page) },
+                next_page_token: if end < unread.len() { Some(end.to_string()) } else { None },
+                result_size_estimate: Some(unread.len() as u32),
+            })
+        })
+    }
+
+    fn get_metadata<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Message>> {
+        Box::pin(async move {
+            let messages = self.messages.lock().unwrap();
+            messages.iter()
+                .find(|m| m.id.as_deref() == Some(id))
+                .cloned()
+                .ok_or_else(|| Error::NotFound(id.to_string()))
+        })
+    }
+
+    fn history_id<'a>(&'a self) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let history = self.history.lock().unwrap();
+            Ok((self.base_history_id + history.len() as u64).to_string())
+        })
+    }
+
+    // 変更ごとに履歴IDを1つ進める
+    fn list_history<'a>(
+        &'a self,
+        start_history_id: &'a str,
+        _page_token: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<HistoryPage>> {
+        Box::pin(async move {
+            let history = self.history.lock().unwrap();
+            let current = self.base_history_id + history.len() as u64;
+            let start = match start_history_id.parse::<u64>() {
+                Ok(start) if start >= self.base_history_id && start <= current => start,
+                _ => return Err(Error::NotFound(format!("history {}", start_history_id))),
+            };
+
+            let list = history[(start - self.base_history_id) as usize..].to_vec();
+            Ok(HistoryPage {
+                history: if list.is_empty() { None } else { Some(list) },
+                next_page_token: None,
+                history_id: Some(current.to_string()),
+            })
+        })
+    }
+
+    fn modify_labels<'a>(
+        &'a self,
+        ids: &'a [&'a str],
+        add: &'a [&'a str],
+        remove: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut messages = self.messages.lock().unwrap();
+            let mut history = self.history.lock().unwrap();
+            for message in messages.iter_mut().filter(|m| ids.contains(&m.id.as_deref().unwrap_or_default())) {
+                let labels = message.label_ids.get_or_insert_with(Vec::new);
+                labels.retain(|l| !remove.contains(&l.as_str()));
+                for label in add {
+                    if !labels.iter().any(|l| l == label) {
+                        labels.push(label.to_string());
+                    }
+                }
+
+                // 実際のAPIと同じくIDと変更後のラベル、付け外ししたラベルだけを記録する
+                let changed = |label_ids: &[&str]| HistoryMessage {
+                    message: Message {
+                        id: message.id.clone(),
+                        thread_id: message.thread_id.clone(),
+                        label_ids: message.label_ids.clone(),
+                        history_id: None,
+                        internal_date: None,
+                        snippet: None,
+                        payload: None,
+                    },
+                    label_ids: label_ids.iter().map(|l| l.to_string()).collect(),
+                };
+                let history_id = self.base_history_id + history.len() as u64 + 1;
+                history.push(History {
+                    id: Some(history_id.to_string()),
+                    labels_added: if add.is_empty() { vec![] } else { vec![changed(add)] },
+                    labels_removed: if remove.is_empty() { vec![] } else { vec![changed(remove)] },
+                    ..History::default()
+                });
+            }
+
+            Ok(())
+        })
+    }
+}
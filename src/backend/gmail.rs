@@ -0,0 +1,61 @@
+use super::MailBackend;
+use crate::error::{Error, Result};
+use crate::message::{HistoryPage, Message, MessageClient, MessageListPage};
+use futures::future::BoxFuture;
+
+impl MailBackend for MessageClient {
+    fn list_unread<'a>(
+        &'a self,
+        page_token: Option<&'a str>,
+        max_results: u32,
+    ) -> BoxFuture<'a, Result<MessageListPage>> {
+        Box::pin(async move {
+            self.get_unread_page(page_token, max_results).await
+        })
+    }
+
+    fn get_metadata<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Message>> {
+        Box::pin(async move {
+            let res_body = self.get_metadata_headers(id).await?;
+            Ok(serde_json::from_str(&res_body)?)
+        })
+    }
+
+    fn get_metadata_batch<'a>(&'a self, ids: &'a [String]) -> BoxFuture<'a, Result<Vec<Result<Message>>>> {
+        Box::pin(async move {
+            let results = self.get_metadata_headers_batch(ids).await?.into_iter()
+                .map(|res_body| Ok(serde_json::from_str(&res_body?)?))
+                .collect();
+            Ok(results)
+        })
+    }
+
+    fn history_id<'a>(&'a self) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            self.get_profile().await?
+                .history_id
+                .ok_or_else(|| Error::NotFound("historyId".to_string()))
+        })
+    }
+
+    fn list_history<'a>(
+        &'a self,
+        start_history_id: &'a str,
+        page_token: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<HistoryPage>> {
+        Box::pin(async move {
+            self.get_history_page(start_history_id, page_token).await
+        })
+    }
+
+    fn modify_labels<'a>(
+        &'a self,
+        ids: &'a [&'a str],
+        add: &'a [&'a str],
+        remove: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.post_modify_labels(ids, add, remove).await
+        })
+    }
+}
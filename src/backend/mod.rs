@@ -0,0 +1,348 @@
+pub mod gmail;
+pub mod fake;
+
+use crate::cache::MetadataCache;
+use crate::error::{Error, Result};
+use crate::message::{History, HistoryPage, Message, MessageListPage, EXCLUDED_LABELS, METADATA_BATCH_LIMIT};
+use futures::{
+    future::BoxFuture,
+    stream::{self, Stream, StreamExt, TryStreamExt},
+};
+use std::collections::{HashMap, HashSet};
+
+// メールサーバーへの操作をまとめたもの
+// Gmail以外にテスト用のインメモリ実装がある
+pub trait MailBackend: Send + Sync {
+    // 未読メッセージのIDを1ページ分取得
+    fn list_unread<'a>(
+        &'a self,
+        page_token: Option<&'a str>,
+        max_results: u32,
+    ) -> BoxFuture<'a, Result<MessageListPage>>;
+
+    // ヘッダー等のメタデータを取得
+    fn get_metadata<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Message>>;
+
+    // 複数のメタデータをまとめて取得
+    // 結果はIDと同じ順で、失敗はメッセージごとに返す
+    fn get_metadata_batch<'a>(&'a self, ids: &'a [String]) -> BoxFuture<'a, Result<Vec<Result<Message>>>> {
+        Box::pin(async move {
+            let mut results = Vec::new();
+            for id in ids {
+                results.push(self.get_metadata(id).await);
+            }
+            Ok(results)
+        })
+    }
+
+    // メールボックスの現在の履歴ID
+    fn history_id<'a>(&'a self) -> BoxFuture<'a, Result<String>>;
+
+    // 履歴IDより後の変更を1ページ分取得
+    // 履歴が残っていなければNotFound相当のエラー
+    fn list_history<'a>(
+        &'a self,
+        start_history_id: &'a str,
+        page_token: Option<&'a str>,
+    ) -> BoxFuture<'a, Result<HistoryPage>>;
+
+    // ラベルの一括付け外し
+    fn modify_labels<'a>(
+        &'a self,
+        ids: &'a [&'a str],
+        add: &'a [&'a str],
+        remove: &'a [&'a str],
+    ) -> BoxFuture<'a, Result<()>>;
+}
+
+// Executeとその取り消しでのラベル操作
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LabelChange {
+    MarkRead,
+    MarkUnread,
+}
+
+impl LabelChange {
+    // Logsに出す名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            LabelChange::MarkRead => "Execute",
+            LabelChange::MarkUnread => "Undo",
+        }
+    }
+
+    pub async fn apply(&self, backend: &dyn MailBackend, ids: &[&str]) -> Result<()> {
+        match self {
+            LabelChange::MarkRead => backend.modify_labels(ids, &[], &["UNREAD"]).await,
+            LabelChange::MarkUnread => backend.modify_labels(ids, &["UNREAD"], &[]).await,
+        }
+    }
+}
+
+// nextPageTokenを辿って未読メッセージをページ単位で流す
+// limit件に達したらそれ以上は取得しない
+pub fn unread_pages(
+    backend: &dyn MailBackend,
+    max_results: u32,
+    limit: usize,
+) -> impl Stream<Item = Result<Vec<Message>>> + '_ {
+    stream::unfold(Some((None, 0)), move |state| async move {
+        let (page_token, fetched): (Option<String>, usize) = state?;
+        if fetched >= limit {
+            return None;
+        }
+
+        let page_size = max_results.min((limit - fetched) as u32);
+        let page = match backend.list_unread(page_token.as_deref(), page_size).await {
+            Ok(page) => page,
+            Err(err) => return Some((Err(err), None)),
+        };
+
+        let messages = page.messages.unwrap_or_default();
+        let next_state = page.next_page_token
+            .map(|token| (Some(token), fetched + messages.len()));
+
+        Some((Ok(messages), next_state))
+    })
+}
+
+// 同期した未読リスト
+pub struct Synced {
+    pub cache: MetadataCache,
+    // メタデータを取得できなかったメッセージとそのエラー
+    // リストにはIDだけ残し、次の同期で取り直す
+    pub failed: Vec<(String, Error)>,
+}
+
+// IDごとのメタデータの取得結果
+#[derive(Default)]
+pub struct FetchedMetadata {
+    pub messages: Vec<Message>,
+    // 取得までの間に削除されたもの
+    pub not_found: HashSet<String>,
+    pub failed: Vec<(String, Error)>,
+}
+
+impl FetchedMetadata {
+    fn push(&mut self, id: &str, result: Result<Message>) {
+        match result {
+            Ok(message) => self.messages.push(message),
+            Err(err) if err.is_not_found() => {
+                self.not_found.insert(id.to_string());
+            },
+            Err(err) => self.failed.push((id.to_string(), err)),
+        }
+    }
+}
+
+// Logsや標準エラーに出す、取得できなかったメッセージの一覧
+pub fn describe_failed(failed: &[(String, Error)]) -> String {
+    let list = failed.iter()
+        .map(|(id, err)| format!("{} ({})", id, err))
+        .collect::<Vec<String>>();
+    format!("cannot fetch metadata of {} messages: {}", failed.len(), list.join(", "))
+}
+
+// キャッシュの履歴IDより後の変更だけを反映して未読リストを作る
+// 履歴IDがない・古すぎて履歴が残っていないときは全件取得し直す
+// on_pageには全件取得で届いたページの未読（メタデータを埋めたもの）と、それまでの件数を渡す
+// concurrencyはメタデータを同時に取得するリクエスト数
+pub async fn sync_unread(
+    backend: &dyn MailBackend,
+    max_results: u32,
+    limit: usize,
+    concurrency: usize,
+    cache: &MetadataCache,
+    on_page: impl Fn(&[Message], usize),
+) -> Result<Synced> {
+    if let Some(history_id) = cache.history_id() {
+        match sync_history(backend, limit, concurrency, cache, history_id).await {
+            Err(err) if err.is_not_found() => {},
+            result => return result,
+        }
+    }
+
+    full_sync(backend, max_results, limit, concurrency, cache, on_page).await
+}
+
+// 未読リストをすべて取得してメタデータを埋める
+// 全ページを待たずに表示できるよう、メタデータはページごとに埋める
+async fn full_sync(
+    backend: &dyn MailBackend,
+    max_results: u32,
+    limit: usize,
+    concurrency: usize,
+    cache: &MetadataCache,
+    on_page: impl Fn(&[Message], usize),
+) -> Result<Synced> {
+    // 取得中の変更を次回拾えるよう先に履歴IDを控える
+    let history_id = backend.history_id().await?;
+
+    let mut message_list = Vec::new();
+    let mut failed = Vec::new();
+    let mut pages = Box::pin(unread_pages(backend, max_results, limit));
+    while let Some(page) = pages.next().await {
+        let (mut filled, mut page_failed) = fill_messages_metadata(backend, &page?, cache, concurrency).await?;
+        on_page(&filled, message_list.len() + filled.len());
+        message_list.append(&mut filled);
+        failed.append(&mut page_failed);
+    }
+
+    Ok(Synced {
+        cache: MetadataCache::new(&message_list, Some(history_id)),
+        failed,
+    })
+}
+
+// キャッシュに履歴を順に適用し、新しく未読になったものだけメタデータを取得する
+// 全件取得と同じくlimit件を超えた古いものは除く
+async fn sync_history(
+    backend: &dyn MailBackend,
+    limit: usize,
+    concurrency: usize,
+    cache: &MetadataCache,
+    start_history_id: &str,
+) -> Result<Synced> {
+    let mut message_list = cache.messages();
+    let mut history_id = start_history_id.to_string();
+    let mut page_token: Option<String> = None;
+
+    loop {
+        let page = backend.list_history(start_history_id, page_token.as_deref()).await?;
+        for history in page.history.unwrap_or_default() {
+            apply_history(&mut message_list, history);
+        }
+        if let Some(id) = page.history_id {
+            history_id = id;
+        }
+
+        page_token = page.next_page_token;
+        if page_token.is_none() {
+            break;
+        }
+    }
+
+    message_list.truncate(limit);
+
+    // 履歴から加えたものはIDとラベルしかない
+    let ids = message_list.iter()
+        .filter(|m| m.payload.is_none())
+        .filter_map(|m| m.id.clone())
+        .collect::<Vec<String>>();
+    let FetchedMetadata { messages, not_found, failed } = fetch_metadata(backend, &ids, concurrency).await?;
+    for message in messages {
+        if let Some(m) = message_list.iter_mut().find(|m| m.id == message.id) {
+            *m = message;
+        }
+    }
+    message_list.retain(|m| m.id.as_ref().is_some_and(|id| !not_found.contains(id)));
+
+    Ok(Synced {
+        cache: MetadataCache::new(&message_list, Some(history_id)),
+        failed,
+    })
+}
+
+// 1回分の変更を未読リストに反映する
+// 全件取得（q=is:unread）と同じく、迷惑メール・ゴミ箱にあるものは載せない
+fn apply_history(message_list: &mut Vec<Message>, history: History) {
+    for deleted in history.messages_deleted {
+        message_list.retain(|m| m.id != deleted.message.id);
+    }
+
+    // 迷惑メール・ゴミ箱に移されたもの
+    let excluded = history.labels_added.iter()
+        .filter(|changed| changed.label_ids.iter().any(|l| EXCLUDED_LABELS.contains(&l.as_str())))
+        .map(|changed| changed.message.id.clone())
+        .collect::<Vec<Option<String>>>();
+
+    let changed = history.messages_added.into_iter()
+        .chain(history.labels_added)
+        .chain(history.labels_removed);
+    for changed in changed {
+        let message = changed.message;
+        let is_listed = message.is_listed_unread() && !excluded.contains(&message.id);
+
+        match message_list.iter().position(|m| m.id == message.id) {
+            Some(i) if is_listed => message_list[i].label_ids = message.label_ids,
+            Some(i) => {
+                message_list.remove(i);
+            },
+            // 新しいものを先頭に
+            None if is_listed => message_list.insert(0, message),
+            None => {},
+        }
+    }
+}
+
+// メッセージのメタデータを埋め、取得できなかったものを返す
+// キャッシュにあるものは取得し直さない
+pub async fn fill_messages_metadata(
+    backend: &dyn MailBackend,
+    message_list: &Vec<Message>,
+    cache: &MetadataCache,
+    concurrency: usize,
+) -> Result<(Vec<Message>, Vec<(String, Error)>)> {
+    let cached = |id: &str| cache.get(id).filter(|m| m.payload.is_some());
+    let ids = message_list.iter()
+        .filter_map(|m| m.id.clone())
+        .filter(|id| cached(id).is_none())
+        .collect::<Vec<String>>();
+    let fetched = fetch_metadata(backend, &ids, concurrency).await?;
+    let not_found = fetched.not_found;
+    let mut messages = fetched.messages.into_iter()
+        .filter_map(|m| m.id.clone().map(|id| (id, m)))
+        .collect::<HashMap<String, Message>>();
+
+    // 未読リストの順に並べる
+    // 削除済みのものは除き、取得に失敗したものはIDだけ残す
+    let filled = message_list.iter()
+        .filter_map(|m| {
+            let id = m.id.as_ref()?;
+            match cached(id) {
+                Some(cached) => Some(cached.clone()),
+                None if not_found.contains(id) => None,
+                None => Some(messages.remove(id).unwrap_or_else(|| m.clone())),
+            }
+        })
+        .collect();
+
+    Ok((filled, fetched.failed))
+}
+
+// IDを指定してメタデータを取得
+// METADATA_BATCH_LIMIT件ずつバッチにまとめ、同時に投げるのはconcurrency件まで
+// バッチの中の失敗はメッセージごとに分けて返し、バッチ全体の失敗だけをエラーにする
+pub async fn fetch_metadata(backend: &dyn MailBackend, ids: &[String], concurrency: usize) -> Result<FetchedMetadata> {
+    // futureは作っただけでは動かない
+    let requests = ids.chunks(METADATA_BATCH_LIMIT)
+        .map(|chunk| backend.get_metadata_batch(chunk))
+        .collect::<Vec<BoxFuture<Result<Vec<Result<Message>>>>>>();
+    let results = stream::iter(requests)
+        .buffered(concurrency.max(1))
+        .try_collect::<Vec<Vec<Result<Message>>>>()
+        .await?;
+
+    let mut fetched = FetchedMetadata::default();
+    let mut retry_ids = Vec::new();
+    for (id, result) in ids.iter().zip(results.into_iter().flatten()) {
+        match result {
+            // バッチの中でクォータ超過などになったものは1件ずつ取り直す
+            Err(err) if err.is_retryable() => retry_ids.push(id),
+            result => fetched.push(id, result),
+        }
+    }
+
+    let retries = retry_ids.iter()
+        .map(|id| backend.get_metadata(id))
+        .collect::<Vec<BoxFuture<Result<Message>>>>();
+    let retried = stream::iter(retries)
+        .buffered(concurrency.max(1))
+        .collect::<Vec<Result<Message>>>()
+        .await;
+    for (id, result) in retry_ids.into_iter().zip(retried) {
+        fetched.push(id, result);
+    }
+
+    Ok(fetched)
+}
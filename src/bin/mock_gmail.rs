@@ -0,0 +1,423 @@
+// ローカル開発・CI用のGmail APIモックサーバー
+//
+// cargo run --bin mock_gmail -- [port] [mailbox.json]
+//
+// ./data/config.json の api_base_url / auth_url / token_url / revoke_url を
+// http://127.0.0.1:<port> 配下に向けると、Googleアカウントなしで一通り動かせる
+// MOCK_GMAIL_RATE_LIMIT=<1秒あたりのリクエスト数> を指定すると超えた分に429を返す
+extern crate serde_json;
+
+use serde_json::{json, Value};
+use url::Url;
+use std::{
+    env,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+type Mailbox = Arc<Mutex<MailboxState>>;
+
+// バッチのレスポンスのパートの区切り
+const RESPONSE_BOUNDARY: &str = "batch_mock_gmail_response";
+
+// 起動時の履歴ID
+// これより前の履歴IDを指定されたら実際のAPIと同じく404を返す
+const BASE_HISTORY_ID: u64 = 1000;
+
+struct MailboxState {
+    messages: Vec<Value>,
+    // batchModifyでのラベル変更の履歴
+    history: Vec<Value>,
+    rate_limit: Option<usize>,
+    // 今の1秒間の開始時刻と受けたリクエスト数
+    window: (Instant, usize),
+}
+
+impl MailboxState {
+    fn history_id(&self) -> u64 {
+        BASE_HISTORY_ID + self.history.len() as u64
+    }
+
+    // 1秒あたりのリクエスト数を超えたか
+    fn over_rate_limit(&mut self) -> bool {
+        let limit = match self.rate_limit {
+            Some(limit) => limit,
+            None => return false,
+        };
+        if self.window.0.elapsed() >= Duration::from_secs(1) {
+            self.window = (Instant::now(), 0);
+        }
+        self.window.1 += 1;
+
+        self.window.1 > limit
+    }
+}
+
+struct Request {
+    method: String,
+    url: Url,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl Response {
+    fn json(status: &'static str, body: Value) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type", "application/json".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    // error_statusとreasonはGmail APIのエラーボディに入れる値（"NOT_FOUND" と "notFound" など）
+    fn error(code: u16, status: &'static str, error_status: &str, reason: &str, message: &str) -> Self {
+        Self::json(status, json!({
+            "error": {
+                "code": code,
+                "message": message,
+                "errors": [{ "message": message, "domain": "global", "reason": reason }],
+                "status": error_status,
+            }
+        }))
+    }
+
+    fn rate_limited() -> Self {
+        let message = "User-rate limit exceeded.";
+        Self::json("429 Too Many Requests", json!({
+            "error": {
+                "code": 429,
+                "message": message,
+                "errors": [{ "message": message, "domain": "usageLimits", "reason": "rateLimitExceeded" }],
+                "status": "RESOURCE_EXHAUSTED",
+            }
+        }))
+    }
+}
+
+fn main() {
+    let args = env::args().collect::<Vec<String>>();
+    let port = args.get(1).map(|p| p.as_str()).unwrap_or("8081");
+    let mailbox = Arc::new(Mutex::new(MailboxState {
+        messages: load_mailbox(args.get(2).map(|p| p.as_str())),
+        history: Vec::new(),
+        rate_limit: env::var("MOCK_GMAIL_RATE_LIMIT").ok().and_then(|l| l.parse().ok()),
+        window: (Instant::now(), 0),
+    }));
+
+    let listener = TcpListener::bind(format!("127.0.0.1:{}", port)).unwrap();
+    println!("mock gmail server ... http://127.0.0.1:{}", port);
+
+    for stream in listener.incoming().flatten() {
+        let mailbox = mailbox.clone();
+        thread::spawn(move || handle(stream, mailbox));
+    }
+}
+
+// [{"id": "1", "from": "Foo <foo@example.com>", "labels": ["UNREAD"]}] 形式
+// 指定がなければ適当なメールボックスを作る
+fn load_mailbox(path: Option<&str>) -> Vec<Value> {
+    let list: Vec<Value> = match path {
+        Some(path) => serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap(),
+        None => {
+            let senders = [
+                "Example News <news@example.com>",
+                "Shop <info@shop.example.co.jp>",
+                "friend@example.org",
+            ];
+            (0..30).map(|i| json!({ "id": format!("{:x}", 0x1000 + i), "from": senders[i % senders.len()] })).collect()
+        }
+    };
+
+    list.into_iter().map(|m| {
+        let labels = m.get("labels").cloned().unwrap_or_else(|| json!(["INBOX", "UNREAD"]));
+
+        let mut headers = vec![json!({ "name": "From", "value": m["from"] })];
+        for (key, name) in &[("subject", "Subject"), ("date", "Date")] {
+            if let Some(value) = m.get(*key) {
+                headers.push(json!({ "name": name, "value": value }));
+            }
+        }
+
+        json!({
+            "id": m["id"],
+            "threadId": m["id"],
+            "labelIds": labels,
+            "historyId": BASE_HISTORY_ID.to_string(),
+            "snippet": m.get("snippet").cloned().unwrap_or_else(|| json!("")),
+            "payload": {
+                "headers": headers,
+            },
+        })
+    }).collect()
+}
+
+fn handle(mut stream: TcpStream, mailbox: Mailbox) {
+    let request = match read_request(&stream) {
+        Some(request) => request,
+        None => return,
+    };
+    println!("{} {}", request.method, request.url.path());
+
+    let over_rate_limit = mailbox.lock().unwrap().over_rate_limit();
+    let response = if over_rate_limit {
+        println!("  -> 429");
+        Response::rate_limited()
+    } else {
+        route(&request, &mailbox)
+    };
+
+    let mut raw = format!("HTTP/1.1 {}\r\nconnection: close\r\ncontent-length: {}\r\n", response.status, response.body.len());
+    for (name, value) in response.headers {
+        raw += &format!("{}: {}\r\n", name, value);
+    }
+    raw += "\r\n";
+    raw += &response.body;
+
+    let _ = stream.write_all(raw.as_bytes());
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let url = Url::parse(&("http://localhost".to_string() + parts.next()?)).ok()?;
+
+    let mut content_length = 0;
+    let mut content_type = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+            if name.eq_ignore_ascii_case("content-type") {
+                content_type = value.trim().to_string();
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(Request { method, url, content_type, body })
+}
+
+fn query(url: &Url, key: &str) -> Option<String> {
+    url.query_pairs().find(|(k, _)| k == key).map(|(_, v)| v.into_owned())
+}
+
+fn route(request: &Request, mailbox: &Mailbox) -> Response {
+    let path = request.url.path();
+    let messages_path = "/gmail/v1/users/me/messages";
+
+    match (request.method.as_str(), path) {
+        ("GET", "/gmail/v1/users/me/profile") => Response::json("200 OK", json!({
+            "emailAddress": "mock@example.com",
+            "historyId": mailbox.lock().unwrap().history_id().to_string(),
+        })),
+        ("GET", "/gmail/v1/users/me/history") => list_history(request, mailbox),
+        ("POST", "/batch/gmail/v1") => batch(request, mailbox),
+        ("GET", p) if p == messages_path => list_messages(request, mailbox),
+        ("POST", p) if p == format!("{}/batchModify", messages_path) => batch_modify(request, mailbox),
+        ("GET", p) if p.starts_with(messages_path) => get_message(&p[messages_path.len() + 1..], mailbox),
+        ("GET", "/o/oauth2/auth") => authorize(request),
+        ("POST", "/token") => Response::json("200 OK", json!({
+            "access_token": "mock-access-token",
+            "refresh_token": "mock-refresh-token",
+            "expires_in": 3600,
+            "scope": "https://mail.google.com/",
+            "token_type": "Bearer",
+        })),
+        ("POST", "/revoke") => Response::json("200 OK", json!({})),
+        _ => Response::error(404, "404 Not Found", "NOT_FOUND", "notFound", "Not Found"),
+    }
+}
+
+fn list_messages(request: &Request, mailbox: &Mailbox) -> Response {
+    let unread_only = query(&request.url, "q").is_some_and(|q| q.contains("is:unread"));
+    let max_results = query(&request.url, "maxResults").and_then(|m| m.parse().ok()).unwrap_or(100usize);
+    let start = query(&request.url, "pageToken").and_then(|t| t.parse().ok()).unwrap_or(0usize);
+
+    let mailbox = mailbox.lock().unwrap();
+    let matched = mailbox.messages.iter()
+        .filter(|m| !unread_only || has_label(m, "UNREAD"))
+        // includeSpamTrashを指定しなければ迷惑メール・ゴミ箱は返さない
+        .filter(|m| !has_label(m, "SPAM") && !has_label(m, "TRASH"))
+        .collect::<Vec<&Value>>();
+
+    let end = matched.len().min(start + max_results);
+    let page = matched[start.min(end)..end].iter()
+        .map(|m| json!({ "id": m["id"], "threadId": m["threadId"] }))
+        .collect::<Vec<Value>>();
+
+    // 実際のAPIと同じく0件のときは messages を返さない
+    let mut body = json!({ "resultSizeEstimate": matched.len() });
+    if !page.is_empty() {
+        body["messages"] = json!(page);
+    }
+    if end < matched.len() {
+        body["nextPageToken"] = json!(end.to_string());
+    }
+
+    Response::json("200 OK", body)
+}
+
+fn get_message(id: &str, mailbox: &Mailbox) -> Response {
+    let mailbox = mailbox.lock().unwrap();
+    match mailbox.messages.iter().find(|m| m["id"] == id) {
+        Some(message) => Response::json("200 OK", message.clone()),
+        None => Response::error(404, "404 Not Found", "NOT_FOUND", "notFound", "Requested entity was not found."),
+    }
+}
+
+fn batch_modify(request: &Request, mailbox: &Mailbox) -> Response {
+    let body: Value = serde_json::from_slice(&request.body).unwrap_or_default();
+    let ids = string_list(&body["ids"]);
+    let add = string_list(&body["addLabelIds"]);
+    let remove = string_list(&body["removeLabelIds"]);
+
+    let mut mailbox = mailbox.lock().unwrap();
+    let mut changed = Vec::new();
+    for message in mailbox.messages.iter_mut().filter(|m| ids.iter().any(|id| m["id"] == id.as_str())) {
+        let mut labels = string_list(&message["labelIds"]);
+        labels.retain(|l| !remove.contains(l));
+        for label in &add {
+            if !labels.contains(label) {
+                labels.push(label.clone());
+            }
+        }
+        message["labelIds"] = json!(labels);
+        changed.push(json!({ "id": message["id"], "threadId": message["threadId"], "labelIds": labels }));
+    }
+
+    // メッセージごとに1件の履歴を残す
+    for message in changed {
+        let history_id = (mailbox.history_id() + 1).to_string();
+        mailbox.messages.iter_mut()
+            .filter(|m| m["id"] == message["id"])
+            .for_each(|m| m["historyId"] = json!(history_id));
+
+        let mut history = json!({ "id": history_id, "messages": [message] });
+        if !add.is_empty() {
+            history["labelsAdded"] = json!([{ "message": message, "labelIds": add }]);
+        }
+        if !remove.is_empty() {
+            history["labelsRemoved"] = json!([{ "message": message, "labelIds": remove }]);
+        }
+        mailbox.history.push(history);
+    }
+
+    Response {
+        status: "204 No Content",
+        headers: vec![],
+        body: String::new(),
+    }
+}
+
+// multipart/mixed のパートごとのリクエストを処理して1つのレスポンスにまとめる
+// 中のリクエストも1件ずつレート制限の対象
+fn batch(request: &Request, mailbox: &Mailbox) -> Response {
+    let boundary = request.content_type.split("boundary=").nth(1).unwrap_or("").trim_matches('"');
+    let body = String::from_utf8_lossy(&request.body);
+
+    let mut raw = String::new();
+    for part in body.split(&format!("--{}", boundary)).skip(1) {
+        if part.starts_with("--") {
+            break;
+        }
+        let (head, inner) = part.trim_start().split_once("\r\n\r\n").unwrap_or((part, ""));
+        let content_id = head.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-id"))
+            .map(|(_, value)| value.trim().trim_matches(|c| c == '<' || c == '>').to_string())
+            .unwrap_or_default();
+
+        let mut request_line = inner.lines().next().unwrap_or("").split_whitespace();
+        let method = request_line.next().unwrap_or("").to_string();
+        let inner_request = Url::parse(&("http://localhost".to_string() + request_line.next().unwrap_or("")))
+            .map(|url| Request { method, url, content_type: String::new(), body: Vec::new() });
+        let over_rate_limit = mailbox.lock().unwrap().over_rate_limit();
+
+        let response = match inner_request {
+            _ if over_rate_limit => Response::rate_limited(),
+            Ok(inner_request) => {
+                println!("  {} {}", inner_request.method, inner_request.url.path());
+                route(&inner_request, mailbox)
+            },
+            Err(_) => Response::error(400, "400 Bad Request", "INVALID_ARGUMENT", "badRequest", "Bad Request"),
+        };
+
+        raw += &format!("--{}\r\nContent-Type: application/http\r\nContent-ID: <response-{}>\r\n\r\n", RESPONSE_BOUNDARY, content_id);
+        raw += &format!("HTTP/1.1 {}\r\nContent-Type: application/json; charset=UTF-8\r\n\r\n{}\r\n", response.status, response.body);
+    }
+    raw += &format!("--{}--\r\n", RESPONSE_BOUNDARY);
+
+    Response {
+        status: "200 OK",
+        headers: vec![("Content-Type", format!("multipart/mixed; boundary={}", RESPONSE_BOUNDARY))],
+        body: raw,
+    }
+}
+
+// startHistoryIdより後の履歴を返す
+fn list_history(request: &Request, mailbox: &Mailbox) -> Response {
+    let mailbox = mailbox.lock().unwrap();
+    let start = query(&request.url, "startHistoryId").and_then(|id| id.parse::<u64>().ok());
+    let start = match start {
+        Some(start) if start >= BASE_HISTORY_ID && start <= mailbox.history_id() => start,
+        _ => return Response::error(404, "404 Not Found", "NOT_FOUND", "notFound", "Requested entity was not found."),
+    };
+
+    let history = &mailbox.history[(start - BASE_HISTORY_ID) as usize..];
+    let mut body = json!({ "historyId": mailbox.history_id().to_string() });
+    if !history.is_empty() {
+        body["history"] = json!(history);
+    }
+
+    Response::json("200 OK", body)
+}
+
+// ブラウザでの同意画面は省略してすぐリダイレクトする
+fn authorize(request: &Request) -> Response {
+    let redirect_uri = query(&request.url, "redirect_uri").unwrap_or_default();
+    let state = query(&request.url, "state").unwrap_or_default();
+
+    let mut location = Url::parse(&redirect_uri).unwrap();
+    location.query_pairs_mut()
+        .append_pair("code", "mock-code")
+        .append_pair("state", &state);
+
+    Response {
+        status: "302 Found",
+        headers: vec![("Location", location.to_string())],
+        body: String::new(),
+    }
+}
+
+fn has_label(message: &Value, label: &str) -> bool {
+    string_list(&message["labelIds"]).iter().any(|l| l == label)
+}
+
+fn string_list(value: &Value) -> Vec<String> {
+    value.as_array()
+        .map(|list| list.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,73 @@
+use crate::data::write_atomic;
+use crate::error::{Error, Result};
+use crate::message::Message;
+use serde::{Serialize, Deserialize};
+use std::{
+    collections::HashMap,
+    fs,
+};
+
+// 未読メッセージのメタデータ（ヘッダー・ラベル・historyId）
+// 次回の起動時には履歴IDからの変更だけを取得する
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct MetadataCache {
+    // 最後に同期したときのメールボックスの履歴ID
+    history_id: Option<String>,
+    messages: Vec<Message>,
+    // メッセージIDからmessagesの位置
+    #[serde(skip)]
+    index: HashMap<String, usize>,
+}
+
+impl MetadataCache {
+    pub fn new(message_list: &Vec<Message>, history_id: Option<String>) -> Self {
+        let mut cache = Self {
+            history_id,
+            messages: message_list.clone(),
+            index: HashMap::new(),
+        };
+        cache.build_index();
+
+        cache
+    }
+
+    // ファイルがなければ空のキャッシュ
+    pub fn load(path: &str) -> Result<Self> {
+        let read = match fs::read_to_string(path) {
+            Ok(read) => read,
+            Err(_) => return Ok(Self::default()),
+        };
+        if read.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let mut cache: Self = serde_json::from_str(&read)
+            .map_err(|source| Error::ParseFile { path: path.to_string(), source })?;
+        cache.build_index();
+
+        Ok(cache)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        write_atomic(path, &serde_json::to_vec(self)?)
+    }
+
+    fn build_index(&mut self) {
+        self.index = self.messages.iter()
+            .enumerate()
+            .filter_map(|(i, m)| m.id.clone().map(|id| (id, i)))
+            .collect();
+    }
+
+    pub fn history_id(&self) -> Option<&str> {
+        self.history_id.as_deref()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Message> {
+        self.index.get(id).map(|i| &self.messages[*i])
+    }
+
+    pub fn messages(&self) -> Vec<Message> {
+        self.messages.clone()
+    }
+}
@@ -0,0 +1,291 @@
+use crate::backend::{LabelChange, MailBackend};
+use crate::config::Config;
+use crate::data::find_undo_entry;
+use crate::error::{Error, Result};
+use crate::message::{self, Message, BATCH_MODIFY_LIMIT};
+use crate::report::DryRunReport;
+use crate::rule::Rule;
+use crate::store::Store;
+
+pub const USAGE: &str = "\
+usage: mark_as_read [command]
+
+commands:
+    (none)                  launch the TUI
+    list                    print unread count per sender
+    mark --from <address>   mark all unread mail from the sender (or *@domain) as read
+    targets list            print the target rules
+    targets add <rule>      add a rule, e.g. \"from:*@news.example.com older:30\"
+    targets remove <index>  remove a rule by its number in targets list
+    run                     mark all unread mail matching the target rules as read
+    journal                 print the Execute history
+    undo [index]            mark the messages of the last (or the given)
+                            journal entry as unread again
+    help                    print this message
+
+options:
+    --dry-run               (mark, run) list the matching messages and write
+                            a report file without marking anything as read";
+
+// サブコマンド
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Tui,
+    List,
+    Mark { from: String, dry_run: bool },
+    TargetsList,
+    TargetsAdd(String),
+    TargetsRemove(String),
+    Run { dry_run: bool },
+    Journal,
+    Undo(Option<usize>),
+    Help,
+}
+
+impl Command {
+    // メールサーバーへの接続が必要か
+    pub fn needs_backend(&self) -> bool {
+        !matches!(
+            self,
+            Command::TargetsList
+                | Command::TargetsAdd(_)
+                | Command::TargetsRemove(_)
+                | Command::Journal
+                | Command::Help
+        )
+    }
+}
+
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Command> {
+    // オプションは位置を問わない
+    let (options, args): (Vec<String>, Vec<String>) = args.partition(|a| a.starts_with("--") && a != "--from");
+    let mut dry_run = false;
+    for option in options {
+        match option.as_str() {
+            "--dry-run" => dry_run = true,
+            "--help" => return Ok(Command::Help),
+            other => return Err(Error::Usage(format!("unknown option: {}", other))),
+        }
+    }
+    let mut args = args.into_iter();
+
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Ok(Command::Tui),
+    };
+
+    let command = match command.as_str() {
+        "list" => Command::List,
+        "mark" => match (args.next().as_deref(), args.next()) {
+            (Some("--from"), Some(from)) => Command::Mark { from, dry_run },
+            _ => return Err(Error::Usage("mark requires --from <address>".to_string())),
+        },
+        "targets" => match (args.next().as_deref(), args.next()) {
+            (Some("list"), None) => Command::TargetsList,
+            (Some("add"), Some(rule)) => Command::TargetsAdd(rule),
+            (Some("remove"), Some(index)) => Command::TargetsRemove(index),
+            _ => return Err(Error::Usage("targets requires list, add <rule> or remove <index>".to_string())),
+        },
+        "run" => Command::Run { dry_run },
+        "journal" => Command::Journal,
+        "undo" => match args.next() {
+            Some(index) => match index.parse() {
+                Ok(index) => Command::Undo(Some(index)),
+                Err(_) => return Err(Error::Usage(format!("invalid journal index: {}", index))),
+            },
+            None => Command::Undo(None),
+        },
+        "help" | "-h" => Command::Help,
+        other => return Err(Error::Usage(format!("unknown command: {}", other))),
+    };
+
+    if let Some(extra) = args.next() {
+        return Err(Error::Usage(format!("unexpected argument: {}", extra)));
+    }
+
+    match command {
+        Command::Mark { .. } | Command::Run { .. } => Ok(command),
+        _ if dry_run => Err(Error::Usage("--dry-run is only for mark and run".to_string())),
+        _ => Ok(command),
+    }
+}
+
+// Targetルールの編集
+pub fn targets(store: &mut Store<'_>, command: &Command) -> Result<()> {
+    match command {
+        Command::TargetsAdd(text) => {
+            let rule = Rule::parse(text)?;
+            store.add_rule(rule.clone())?;
+            store.push_log("Add", &rule.to_string())?;
+        },
+        Command::TargetsRemove(index) => {
+            let removed = match index.parse::<usize>() {
+                Ok(i) => store.remove_rule(i)?,
+                Err(_) => None,
+            };
+            let rule = removed.ok_or_else(|| Error::Usage(format!("no target rule {}", index)))?;
+            store.push_log("Delete", &rule.to_string())?;
+        },
+        _ => {
+            for (i, rule) in store.rules().iter().enumerate() {
+                println!("{:>3}  {}", i, rule);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// 送信者ごとの未読数を多い順に出す
+pub fn list(message_list: &Vec<Message>) {
+    for summary in message::get_sender_count_list(message_list) {
+        println!("{:>6}  {}", summary.count, summary.sender);
+    }
+}
+
+// 指定アドレス（*@domainならドメイン全体）からの未読を既読にする
+pub async fn mark(
+    config: &Config<'_>,
+    store: &mut Store<'_>,
+    backend: &dyn MailBackend,
+    message_list: &Vec<Message>,
+    from: &str,
+    dry_run: bool,
+) -> Result<()> {
+    let rule = Rule::parse(&format!("from:{}", from))?;
+    let targets = message::collect_targets(message_list, &vec![rule])?;
+    if dry_run {
+        return write_report(config, store, message_list, &targets);
+    }
+
+    let (_, failed) = execute(store, backend, LabelChange::MarkRead, &targets, &id_list(&targets)).await?;
+
+    check_failed(failed)
+}
+
+// Targetルールに一致する未読を既読にする
+pub async fn run(
+    config: &Config<'_>,
+    store: &mut Store<'_>,
+    backend: &dyn MailBackend,
+    message_list: &Vec<Message>,
+    dry_run: bool,
+) -> Result<()> {
+    let targets = message::collect_targets(message_list, store.rules())?;
+    if dry_run {
+        return write_report(config, store, message_list, &targets);
+    }
+
+    let (_, failed) = execute(store, backend, LabelChange::MarkRead, &targets, &id_list(&targets)).await?;
+
+    check_failed(failed)
+}
+
+// Executeの履歴を出す
+pub fn journal(store: &Store<'_>) -> Result<()> {
+    for (i, entry) in store.journal().iter().enumerate() {
+        println!(
+            "{:>3}  {}  {:>6} messages  {}{}",
+            i,
+            entry.timestamp,
+            entry.ids.len(),
+            entry.targets.join(", "),
+            if entry.undone { "  (undone)" } else { "" },
+        );
+    }
+
+    Ok(())
+}
+
+// 履歴のメッセージを未読に戻す
+pub async fn undo(store: &mut Store<'_>, backend: &dyn MailBackend, index: Option<usize>) -> Result<()> {
+    let index = find_undo_entry(store.journal(), index)?;
+    let ids = store.journal()[index].ids.clone();
+
+    let (_, failed) = execute(store, backend, LabelChange::MarkUnread, &Vec::new(), &ids).await?;
+
+    // 一部失敗したら再実行できるよう残しておく
+    if failed == 0 {
+        store.set_undone(index)?;
+    }
+
+    check_failed(failed)
+}
+
+// 既読化はせず対象を表示してレポートファイルに書き出す
+fn write_report(
+    config: &Config<'_>,
+    store: &mut Store<'_>,
+    message_list: &Vec<Message>, targets: &Vec<(String, Vec<String>)>) -> Result<()> {
+    let report = DryRunReport::new(message_list, targets);
+    print!("{}", report.to_text());
+
+    report.write(config.report_path)?;
+    store.push_log("Dry-run", &format!("{} messages (report: {})", report.total, config.report_path))?;
+    eprintln!("dry-run report ... {}", config.report_path);
+
+    Ok(())
+}
+
+fn check_failed(failed: usize) -> Result<()> {
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::PartialFailure(failed)),
+    }
+}
+
+fn id_list(targets: &Vec<(String, Vec<String>)>) -> Vec<String> {
+    targets.iter()
+        .flat_map(|(_, ids)| ids.iter().cloned())
+        .collect()
+}
+
+// チャンクごとにbatchModifyを呼び、成功したIDと失敗したチャンク数を返す
+// 既読化はチャンクが成功するたびにtargetsごとの履歴に記録する
+async fn execute(
+    store: &mut Store<'_>,
+    backend: &dyn MailBackend,
+    change: LabelChange,
+    targets: &Vec<(String, Vec<String>)>,
+    id_list: &Vec<String>,
+) -> Result<(Vec<String>, usize)> {
+    if id_list.is_empty() {
+        println!("no target messages");
+        return Ok((Vec::new(), 0));
+    }
+
+    store.push_log(change.name(), &format!("start {} messages", id_list.len()))?;
+
+    let chunk_count = id_list.len().div_ceil(BATCH_MODIFY_LIMIT);
+    let mut succeeded = Vec::new();
+    let mut failed = 0;
+    let mut journal_index = None;
+
+    for (i, chunk) in id_list.chunks(BATCH_MODIFY_LIMIT).enumerate() {
+        let ids = chunk.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        let result = match change.apply(backend, &ids).await {
+            Ok(_) => {
+                if change == LabelChange::MarkRead {
+                    journal_index = store.journal_chunk(journal_index, targets, &chunk.to_vec())?;
+                }
+                succeeded.extend(chunk.iter().cloned());
+                format!("chunk {}/{} ok ({} messages)", i + 1, chunk_count, chunk.len())
+            },
+            Err(err) => {
+                failed += 1;
+                format!("chunk {}/{} failed: {}", i + 1, chunk_count, err)
+            }
+        };
+
+        println!("{}", result);
+        store.push_log(change.name(), &result)?;
+    }
+
+    store.push_log(change.name(), &format!("complete {} messages", succeeded.len()))?;
+    match change {
+        LabelChange::MarkRead => println!("mark as read ... {} messages", succeeded.len()),
+        LabelChange::MarkUnread => println!("mark as unread ... {} messages", succeeded.len()),
+    }
+
+    Ok((succeeded, failed))
+}
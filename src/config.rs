@@ -1,15 +1,67 @@
-use crate::request::secret;
+use crate::error::{Error, Result};
+use crate::request::{
+    secret::{self, Endpoints},
+    token::Token,
+};
+use serde::Deserialize;
 use std::{
+    env,
     fs,
     path::Path,
 };
 
+// ./data/config.json で上書きできる項目
+// 環境変数が設定されていればそちらを優先する
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct ConfigFile {
+    api_base_url: Option<String>,
+    auth_url: Option<String>,
+    token_url: Option<String>,
+    revoke_url: Option<String>,
+    max_results: Option<u32>,
+    unread_limit: Option<usize>,
+    refresh_interval: Option<u64>,
+    concurrency: Option<usize>,
+    quota_per_second: Option<u32>,
+}
+
+// 接続先のメールサーバー
+#[derive(Debug, PartialEq)]
+pub enum BackendKind {
+    Gmail,
+    // fake_mailbox_path のシナリオで動くインメモリ実装
+    Fake,
+}
+
 #[derive(Debug)]
 pub struct Config<'a> {
     pub token_path: &'a str,
     pub mark_list_path: &'a str,
     pub log_path: &'a str,
-    pub valid_token: Option<String>,
+    pub journal_path: &'a str,
+    pub config_path: &'a str,
+    pub report_path: &'a str,
+    pub fake_mailbox_path: &'a str,
+    // 未読メッセージのメタデータのキャッシュ
+    pub cache_path: &'a str,
+    // 同時に起動したプロセスがデータを上書きし合わないためのロック
+    pub lock_path: &'a str,
+    pub backend: BackendKind,
+    // Gmail APIの接続先（ローカルのモックサーバーに向ける場合に変更）
+    pub api_base_url: String,
+    pub endpoints: Endpoints,
+    pub valid_token: Option<Token>,
+    // 未読リスト1ページあたりの取得件数（Gmail側の上限は500）
+    pub max_results: u32,
+    // 未読リストの取得件数の上限
+    pub unread_limit: usize,
+    // TUIで未読リストを同期し直す間隔（秒、0なら自動では同期しない）
+    pub refresh_interval: u64,
+    // メタデータを同時に取得するリクエスト数
+    pub concurrency: usize,
+    // 1秒あたりに使うGmail APIのクォータ単位（ユーザーごとの上限は250、0なら制限しない）
+    pub quota_per_second: u32,
 }
 
 impl Default for Config<'_> {
@@ -18,7 +70,21 @@ impl Default for Config<'_> {
             token_path: "./data/token",
             mark_list_path: "./data/mark_list.json",
             log_path: "./data/log.json",
+            journal_path: "./data/journal.json",
+            config_path: "./data/config.json",
+            report_path: "./data/dry_run_report.txt",
+            fake_mailbox_path: "./data/fake_mailbox.json",
+            cache_path: "./data/metadata_cache.json",
+            lock_path: "./data/.lock",
+            backend: BackendKind::Gmail,
+            api_base_url: "https://gmail.googleapis.com".to_string(),
+            endpoints: Endpoints::default(),
             valid_token: None,
+            max_results: 500,
+            unread_limit: 10000,
+            refresh_interval: 60,
+            concurrency: 10,
+            quota_per_second: 200,
         }
     }
 }
@@ -28,36 +94,124 @@ impl Config<'_> {
         Config::default()
     }
 
-    pub fn init(&mut self) {
+    pub fn init(&mut self) -> Result<()> {
+        self.load_file()?;
+        self.load_env();
+
         create_essential_files(vec![
             self.token_path,
             self.mark_list_path,
             self.log_path,
-        ]);
-        self.set_token();
+            self.journal_path,
+        ])?;
+
+        Ok(())
     }
 
-    fn set_token(&mut self) {
-        let mut token = fs::read_to_string(self.token_path).unwrap_or_default();
+    fn load_file(&mut self) -> Result<()> {
+        let content = fs::read_to_string(self.config_path).unwrap_or_default();
+        if content.is_empty() {
+            return Ok(());
+        }
+
+        let file: ConfigFile = serde_json::from_str(&content)
+            .map_err(|source| Error::ParseFile { path: self.config_path.to_string(), source })?;
+        if let Some(url) = file.api_base_url {
+            self.api_base_url = url;
+        }
+        if let Some(url) = file.auth_url {
+            self.endpoints.auth_url = url;
+        }
+        if let Some(url) = file.token_url {
+            self.endpoints.token_url = url;
+        }
+        if let Some(url) = file.revoke_url {
+            self.endpoints.revoke_url = url;
+        }
+        if let Some(max_results) = file.max_results {
+            self.max_results = max_results;
+        }
+        if let Some(limit) = file.unread_limit {
+            self.unread_limit = limit;
+        }
+        if let Some(interval) = file.refresh_interval {
+            self.refresh_interval = interval;
+        }
+        if let Some(concurrency) = file.concurrency {
+            self.concurrency = concurrency.max(1);
+        }
+        if let Some(quota) = file.quota_per_second {
+            self.quota_per_second = quota;
+        }
+
+        Ok(())
+    }
 
-        if !&token.is_empty() {
-            println!("oauth2 token is already set");
-        } else {
-            token = secret::get_oauth2_token().unwrap();
-            fs::write(self.token_path, &token).unwrap();
-            println!("get oauth2 token ... ok");
+    fn load_env(&mut self) {
+        if let Ok(url) = env::var("MARK_AS_READ_API_BASE") {
+            self.api_base_url = url;
+        }
+        if let Ok(url) = env::var("MARK_AS_READ_AUTH_URL") {
+            self.endpoints.auth_url = url;
+        }
+        if let Ok(url) = env::var("MARK_AS_READ_TOKEN_URL") {
+            self.endpoints.token_url = url;
+        }
+        if let Ok(url) = env::var("MARK_AS_READ_REVOKE_URL") {
+            self.endpoints.revoke_url = url;
+        }
+        if let Some(limit) = env::var("MARK_AS_READ_UNREAD_LIMIT").ok().and_then(|l| l.parse().ok()) {
+            self.unread_limit = limit;
+        }
+        if let Some(interval) = env::var("MARK_AS_READ_REFRESH_INTERVAL").ok().and_then(|i| i.parse().ok()) {
+            self.refresh_interval = interval;
+        }
+        if let Some(concurrency) = env::var("MARK_AS_READ_CONCURRENCY").ok().and_then(|c| c.parse::<usize>().ok()) {
+            self.concurrency = concurrency.max(1);
         }
+        if let Some(quota) = env::var("MARK_AS_READ_QUOTA_PER_SECOND").ok().and_then(|q| q.parse().ok()) {
+            self.quota_per_second = quota;
+        }
+        if env::var("MARK_AS_READ_BACKEND").is_ok_and(|b| b == "fake") {
+            self.backend = BackendKind::Fake;
+        }
+    }
+
+    pub fn set_token(&mut self) -> Result<()> {
+        // リフレッシュトークンを持っていない旧形式のトークンは取り直す
+        let token = match Token::load(self.token_path) {
+            Some(token) if token.refresh_token.is_some() => {
+                println!("oauth2 token is already set");
+                token
+            },
+            _ => {
+                let token = secret::get_oauth2_token(&self.endpoints)?;
+                token.save(self.token_path)
+                    .map_err(|source| Error::File { path: self.token_path.to_string(), source })?;
+                println!("get oauth2 token ... ok");
+                token
+            }
+        };
 
         self.valid_token = Some(token);
+
+        Ok(())
     }
 }
 
-#[allow(unused_must_use)]
-fn create_essential_files(paths: Vec<&str>) {
+fn create_essential_files(paths: Vec<&str>) -> Result<()> {
     for p in paths {
-        if !Path::new(p).exists() {
-            fs::File::create(p).unwrap();
+        let path = Path::new(p);
+        if path.exists() {
+            continue;
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|source| Error::File { path: p.to_string(), source })?;
         }
+        fs::File::create(path).map_err(|source| Error::File { path: p.to_string(), source })?;
     }
+
+    Ok(())
 }
 
@@ -0,0 +1,69 @@
+use crate::error::{Error, Result};
+use serde::{Serialize, Deserialize};
+use std::fs;
+
+// Executeで既読にしたメッセージの記録（Undo用）
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournalEntry {
+    pub timestamp: String,
+    pub targets: Vec<String>,
+    pub ids: Vec<String>,
+    #[serde(default)]
+    pub undone: bool,
+}
+
+// JSON配列のファイルを読み込む
+pub fn read_list(path: &str) -> Result<Vec<String>> {
+    let read = fs::read_to_string(path)
+        .map_err(|source| Error::File { path: path.to_string(), source })?;
+    let mut list: Vec<String> = Vec::new();
+    if !read.is_empty() {
+        list = serde_json::from_str(&read)
+            .map_err(|source| Error::ParseFile { path: path.to_string(), source })?;
+    }
+
+    Ok(list)
+}
+
+pub fn write_list(path: &str, list: &Vec<String>) -> Result<()> {
+    write_atomic(path, &serde_json::to_vec(list)?)
+}
+
+pub fn read_journal(path: &str) -> Result<Vec<JournalEntry>> {
+    let read = fs::read_to_string(path)
+        .map_err(|source| Error::File { path: path.to_string(), source })?;
+    if read.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    serde_json::from_str(&read)
+        .map_err(|source| Error::ParseFile { path: path.to_string(), source })
+}
+
+pub fn write_journal(path: &str, journal: &Vec<JournalEntry>) -> Result<()> {
+    write_atomic(path, &serde_json::to_vec(journal)?)
+}
+
+// 一時ファイルに書いてから置き換える
+// 書き込み中に落ちても元のファイルが壊れないように
+pub fn write_atomic(path: &str, bytes: &[u8]) -> Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, bytes)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|source| Error::File { path: path.to_string(), source })
+}
+
+// 取り消すエントリの位置
+// 指定がなければ最後のまだ取り消していないもの
+pub fn find_undo_entry(journal: &Vec<JournalEntry>, index: Option<usize>) -> Result<usize> {
+    match index {
+        Some(index) => match journal.get(index) {
+            Some(entry) if entry.undone => Err(Error::Undo(format!("entry {} is already undone", index))),
+            Some(_) => Ok(index),
+            None => Err(Error::Undo(format!("no journal entry {}", index))),
+        },
+        None => journal.iter()
+            .rposition(|entry| !entry.undone)
+            .ok_or_else(|| Error::Undo("nothing to undo".to_string())),
+    }
+}
@@ -0,0 +1,117 @@
+use thiserror::Error;
+use serde::Deserialize;
+use std::io;
+
+// アプリ全体のエラー
+// Display はそのままLogsペインや標準エラーに出す文言
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("authorization failed: {0}")]
+    Auth(String),
+    #[error("refresh token has been revoked, restart mark_as_read to authorize again")]
+    TokenRevoked,
+    #[error("cannot read ./data/client_secret.json: {0}")]
+    ClientSecret(io::Error),
+    #[error("Gmail API error {code} ({status}): {message}")]
+    Api {
+        code: u16,
+        status: String,
+        message: String,
+        // errors[0].reason（rateLimitExceeded など）
+        reason: String,
+    },
+    #[error("HTTP {status} from {url}")]
+    Http {
+        status: u16,
+        url: String,
+    },
+    #[error("network error: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("error reading {path}: {source}")]
+    File {
+        path: String,
+        source: io::Error,
+    },
+    #[error("error parsing {path}: {source}")]
+    ParseFile {
+        path: String,
+        source: serde_json::Error,
+    },
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("terminal error: {0}")]
+    Terminal(#[from] crossterm::ErrorKind),
+    #[error("unexpected response: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("message not found: {0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Usage(String),
+    #[error("invalid rule: {0}")]
+    Rule(String),
+    #[error("cannot undo: {0}")]
+    Undo(String),
+    #[error("another instance is running ({0} is locked)")]
+    Locked(String),
+    #[error("unexpected batch response: {0}")]
+    Batch(String),
+    #[error("{0} batchModify request(s) failed")]
+    PartialFailure(usize),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// Gmail APIのエラーレスポンス
+// {"error": {"code": 403, "message": "...", "status": "PERMISSION_DENIED", "errors": [...]}}
+#[derive(Deserialize, Debug)]
+struct ApiErrorBody {
+    error: ApiErrorDetail,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiErrorDetail {
+    code: u16,
+    message: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    errors: Vec<ApiErrorReason>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiErrorReason {
+    #[serde(default)]
+    reason: String,
+}
+
+impl Error {
+    // 存在しないID・残っていない履歴
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::Api { code: 404, .. } | Error::Http { status: 404, .. } | Error::NotFound(_))
+    }
+
+    // クォータ超過（429・403 rateLimitExceeded）とサーバー側の一時的なエラー
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Api { code: 403, reason, .. } => reason == "rateLimitExceeded" || reason == "userRateLimitExceeded",
+            Error::Api { code, .. } | Error::Http { status: code, .. } => *code == 429 || *code >= 500,
+            _ => false,
+        }
+    }
+
+    // ステータスコードとレスポンスボディからエラーを組み立てる
+    pub fn from_response(status: u16, url: &str, body: &str) -> Self {
+        match serde_json::from_str::<ApiErrorBody>(body) {
+            Ok(body) => Error::Api {
+                code: body.error.code,
+                status: body.error.status,
+                message: body.error.message,
+                reason: body.error.errors.into_iter().next().map(|e| e.reason).unwrap_or_default(),
+            },
+            Err(_) => Error::Http {
+                status,
+                url: url.to_string(),
+            },
+        }
+    }
+}
@@ -5,8 +5,8 @@ use crossterm::{
         KeyEvent
     }
 };
+use tokio::sync::mpsc;
 use std::{
-    sync::mpsc,
     thread,
     time::{
         Duration,
@@ -20,17 +20,14 @@ pub enum Event<I> {
 }
 
 pub struct Events {
-    rx: mpsc::Receiver<Event<KeyEvent>>,
-    _tx: mpsc::Sender<Event<KeyEvent>>,
+    rx: mpsc::UnboundedReceiver<Event<KeyEvent>>,
 }
 
 impl Events {
     pub fn new(duration: u64) -> Self {
         // チャネル送受信機生成
-        let (tx, rx) = mpsc::channel();
-
-        // 後でmoveするので送信機の情報をコピー
-        let event_tx = tx.clone();
+        // キー入力はスレッドで待ち、tokioのチャネルでループに渡す
+        let (event_tx, rx) = mpsc::unbounded_channel();
 
         // xxミリ秒間隔でキー受付
         let tick_rate = Duration::from_millis(duration);
@@ -51,24 +48,29 @@ impl Events {
                 if event::poll(timeout).unwrap() {
                     // キー入力をrxにsend
                     if let CEvent::Key(key) = event::read().unwrap() {
-                        event_tx.send(Event::Input(key)).unwrap();
+                        // 受信側が終了していたらスレッドも終える
+                        if event_tx.send(Event::Input(key)).is_err() {
+                            break;
+                        }
                     }
                 }
 
                 // 経過秒が200ミリ秒を超えたらtickを送信して経過秒をリセット
                 if last_tick.elapsed() >= tick_rate {
-                    if let Ok(_) = event_tx.send(Event::Tick) {
-                        last_tick = Instant::now();
+                    if event_tx.send(Event::Tick).is_err() {
+                        break;
                     }
+                    last_tick = Instant::now();
                 }
             }
         });
 
-        // ループ中の送受信機を外だし
-        Self { rx, _tx: tx }
+        // ループ中の受信機を外だし
+        Self { rx }
     }
 
-    pub fn next(&self) -> Result<Event<KeyEvent>, mpsc::RecvError> {
-        self.rx.recv()
+    // 入力スレッドが終わっていたらNone
+    pub async fn next(&mut self) -> Option<Event<KeyEvent>> {
+        self.rx.recv().await
     }
 }
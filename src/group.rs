@@ -0,0 +1,320 @@
+use crate::message::SenderSummary;
+use crate::rule::Rule;
+use crate::sender::Sender;
+use chrono::{DateTime, Utc};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
+// 2階層で1つの公開サフィックスになるもの（co.jp など）
+// Public Suffix List の全体は持たず、よく見るものだけ
+const SECOND_LEVEL_SUFFIXES: [&str; 24] = [
+    "co.jp", "ne.jp", "or.jp", "ac.jp", "go.jp", "ad.jp", "ed.jp", "gr.jp", "lg.jp",
+    "co.uk", "org.uk", "ac.uk", "gov.uk",
+    "com.au", "net.au", "org.au",
+    "co.nz", "co.kr", "com.cn", "com.tw", "com.hk", "com.sg", "com.br", "co.in",
+];
+
+// Fromペインのまとめ方
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grouping {
+    Address,
+    Domain,
+    // news.example.co.jp と shop.example.co.jp を example.co.jp にまとめる
+    RegistrableDomain,
+}
+
+impl Grouping {
+    pub fn next(self) -> Self {
+        match self {
+            Grouping::Address => Grouping::Domain,
+            Grouping::Domain => Grouping::RegistrableDomain,
+            Grouping::RegistrableDomain => Grouping::Address,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Grouping::Address => "address",
+            Grouping::Domain => "domain",
+            Grouping::RegistrableDomain => "site",
+        }
+    }
+
+    // アドレスの属するグループ
+    pub fn key(&self, address: &str) -> String {
+        let domain = address.rsplit('@').next().unwrap_or("");
+        match self {
+            Grouping::Address => address.to_string(),
+            Grouping::Domain => domain.to_string(),
+            Grouping::RegistrableDomain => registrable_domain(domain).to_string(),
+        }
+    }
+}
+
+// Fromペインの並び順
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortOrder {
+    // 未読の多い順
+    Count,
+    // 名前順
+    Name,
+    // 最新のメッセージが新しい順
+    Recent,
+}
+
+impl SortOrder {
+    pub fn next(self) -> Self {
+        match self {
+            SortOrder::Count => SortOrder::Name,
+            SortOrder::Name => SortOrder::Recent,
+            SortOrder::Recent => SortOrder::Count,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortOrder::Count => "count",
+            SortOrder::Name => "name",
+            SortOrder::Recent => "recent",
+        }
+    }
+
+    // 同順位は名前順
+    fn compare(&self, a: &FromRow, b: &FromRow) -> Ordering {
+        let by_name = a.sort_name().cmp(&b.sort_name());
+        match self {
+            SortOrder::Count => b.count().cmp(&a.count()).then(by_name),
+            SortOrder::Name => by_name,
+            // 日付のないものは最後
+            SortOrder::Recent => b.newest().cmp(&a.newest()).then(by_name),
+        }
+    }
+}
+
+// 登録可能ドメイン（eTLD+1）
+pub fn registrable_domain(domain: &str) -> &str {
+    let labels = domain.split('.').collect::<Vec<&str>>();
+    let suffix_len = match labels.len() {
+        len if len >= 2 && SECOND_LEVEL_SUFFIXES.contains(&labels[len - 2..].join(".").as_str()) => 2,
+        _ => 1,
+    };
+
+    match labels.len().checked_sub(suffix_len + 1) {
+        Some(start) => {
+            let skip = labels[..start].iter().map(|l| l.len() + 1).sum::<usize>();
+            &domain[skip..]
+        },
+        None => domain,
+    }
+}
+
+// Fromペインの1行
+#[derive(Debug, Clone, PartialEq)]
+pub enum FromRow {
+    // ドメインの行
+    Group {
+        key: String,
+        count: usize,
+        newest: Option<DateTime<Utc>>,
+        senders: usize,
+        expanded: bool,
+        // 送信者のいずれかがTargetルールに一致する
+        targeted: bool,
+    },
+    Sender {
+        sender: Sender,
+        count: usize,
+        newest: Option<DateTime<Utc>>,
+        // ドメインの行の下に展開されたもの
+        nested: bool,
+        targeted: bool,
+    },
+}
+
+impl FromRow {
+    pub fn label(&self) -> String {
+        match self {
+            FromRow::Group { key, senders, expanded, .. } => {
+                let mark = if *expanded { "▾" } else { "▸" };
+                let unit = if *senders == 1 { "sender" } else { "senders" };
+                format!("{} {} ({} {})", mark, key, senders, unit)
+            },
+            FromRow::Sender { sender, nested: true, .. } => format!("    {}", sender),
+            FromRow::Sender { sender, .. } => sender.to_string(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        match self {
+            FromRow::Group { count, .. } | FromRow::Sender { count, .. } => *count,
+        }
+    }
+
+    pub fn targeted(&self) -> bool {
+        match self {
+            FromRow::Group { targeted, .. } | FromRow::Sender { targeted, .. } => *targeted,
+        }
+    }
+
+    pub fn newest(&self) -> Option<DateTime<Utc>> {
+        match self {
+            FromRow::Group { newest, .. } | FromRow::Sender { newest, .. } => *newest,
+        }
+    }
+
+    // 並べ替えや絞り込みをしても同じ行を選択し続けるための識別子
+    pub fn id(&self) -> String {
+        match self {
+            FromRow::Group { key, .. } => format!("group:{}", key),
+            FromRow::Sender { sender, .. } => sender.address.clone(),
+        }
+    }
+
+    fn sort_name(&self) -> String {
+        match self {
+            FromRow::Group { key, .. } => key.clone(),
+            FromRow::Sender { sender, .. } => sender.to_string().to_lowercase(),
+        }
+    }
+
+    // この行をTargetに追加するときのルール
+    pub fn rule(&self, grouping: Grouping) -> Rule {
+        match self {
+            FromRow::Group { key, .. } => {
+                let from = match grouping {
+                    Grouping::RegistrableDomain => format!("*@*.{}", key),
+                    _ => format!("*@{}", key),
+                };
+                Rule {
+                    from: Some(from),
+                    ..Rule::default()
+                }
+            },
+            FromRow::Sender { sender, .. } => Rule::from_address(&sender.address),
+        }
+    }
+}
+
+// 送信者ごとの未読数からFromペインの行を作る
+// 展開したグループの下に送信者を並べる
+// filterは送信者の表示名・アドレスやグループ名の部分一致（大文字小文字は区別しない）
+// targetedはTargetルールに一致するメッセージがある送信者のアドレス
+pub fn build_rows(
+    summaries: Vec<SenderSummary>,
+    grouping: Grouping,
+    order: SortOrder,
+    expanded: &HashSet<String>,
+    filter: &str,
+    targeted: &HashSet<String>,
+) -> Vec<FromRow> {
+    let filter = filter.to_lowercase();
+    let matches = |text: &str| filter.is_empty() || text.to_lowercase().contains(&filter);
+
+    let sender_row = |summary: SenderSummary, nested: bool| FromRow::Sender {
+        targeted: targeted.contains(&summary.sender.address),
+        sender: summary.sender,
+        count: summary.count,
+        newest: summary.newest,
+        nested,
+    };
+
+    if grouping == Grouping::Address {
+        let mut rows = summaries.into_iter()
+            .filter(|s| matches(&s.sender.to_string()))
+            .map(|s| sender_row(s, false))
+            .collect::<Vec<FromRow>>();
+        rows.sort_by(|a, b| order.compare(a, b));
+        return rows;
+    }
+
+    let mut index_list: HashMap<String, usize> = HashMap::new();
+    let mut groups: Vec<(String, Vec<SenderSummary>)> = Vec::new();
+    for summary in summaries {
+        let key = grouping.key(&summary.sender.address);
+        let i = *index_list.entry(key.clone()).or_insert_with(|| {
+            groups.push((key, Vec::new()));
+            groups.len() - 1
+        });
+        groups[i].1.push(summary);
+    }
+
+    let mut group_rows = Vec::new();
+    for (key, summaries) in groups {
+        // グループ名が一致すれば送信者はすべて表示
+        let key_matched = matches(&key);
+        if !key_matched && !summaries.iter().any(|s| matches(&s.sender.to_string())) {
+            continue;
+        }
+
+        let group = FromRow::Group {
+            count: summaries.iter().map(|s| s.count).sum(),
+            newest: summaries.iter().filter_map(|s| s.newest).max(),
+            senders: summaries.len(),
+            expanded: expanded.contains(&key),
+            targeted: summaries.iter().any(|s| targeted.contains(&s.sender.address)),
+            key,
+        };
+
+        let mut sender_rows = summaries.into_iter()
+            .filter(|s| key_matched || matches(&s.sender.to_string()))
+            .map(|s| sender_row(s, true))
+            .collect::<Vec<FromRow>>();
+        sender_rows.sort_by(|a, b| order.compare(a, b));
+
+        group_rows.push((group, sender_rows));
+    }
+    group_rows.sort_by(|a, b| order.compare(&a.0, &b.0));
+
+    let mut rows = Vec::new();
+    for (group, sender_rows) in group_rows {
+        let is_expanded = match group {
+            FromRow::Group { expanded, .. } => expanded,
+            _ => false,
+        };
+        rows.push(group);
+        if is_expanded {
+            rows.extend(sender_rows);
+        }
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registrable_domain_keeps_one_label_above_suffix() {
+        assert_eq!(registrable_domain("example.com"), "example.com");
+        assert_eq!(registrable_domain("news.example.com"), "example.com");
+        assert_eq!(registrable_domain("a.b.news.example.com"), "example.com");
+    }
+
+    #[test]
+    fn registrable_domain_knows_second_level_suffixes() {
+        assert_eq!(registrable_domain("shop.example.co.jp"), "example.co.jp");
+        assert_eq!(registrable_domain("example.co.jp"), "example.co.jp");
+        assert_eq!(registrable_domain("mail.example.org.uk"), "example.org.uk");
+        // 2階層目がsuffixでなければ1階層のTLDとして扱う
+        assert_eq!(registrable_domain("news.example.jp"), "example.jp");
+    }
+
+    #[test]
+    fn registrable_domain_leaves_short_domains() {
+        assert_eq!(registrable_domain("co.jp"), "co.jp");
+        assert_eq!(registrable_domain("localhost"), "localhost");
+        assert_eq!(registrable_domain(""), "");
+    }
+
+    #[test]
+    fn grouping_key() {
+        let address = "info@shop.example.co.jp";
+
+        assert_eq!(Grouping::Address.key(address), address);
+        assert_eq!(Grouping::Domain.key(address), "shop.example.co.jp");
+        assert_eq!(Grouping::RegistrableDomain.key(address), "example.co.jp");
+    }
+}
@@ -0,0 +1,17 @@
+// TUI・サブコマンド・テストから使うモジュール
+pub mod config;
+pub mod error;
+pub mod request;
+pub mod message;
+pub mod backend;
+pub mod events;
+pub mod app;
+pub mod terminal;
+pub mod cli;
+pub mod data;
+pub mod report;
+pub mod rule;
+pub mod sender;
+pub mod group;
+pub mod store;
+pub mod cache;
@@ -1,72 +1,157 @@
-mod config;
-mod request;
-mod message;
-mod events;
-mod app;
-
-use config::Config;
-use request::{
-    client::GClient,
+use mark_as_read::{
+    backend::{self, MailBackend, fake::FakeBackend},
+    app::App,
+    cache::MetadataCache,
+    cli::{self, Command},
+    config::{BackendKind, Config},
+    error::{self, Result},
+    events::{EventState, events::{Event, Events}},
+    message::MessageClient,
+    request::client::GClient,
+    store::Store,
+    terminal::{self, TerminalGuard},
 };
-use message::{MessageClient};
-use events::events::{Event, Events};
-use app::App;
-use crate::events::EventState;
 
 use tui::{
     backend::CrosstermBackend,
     Terminal,
 };
-use crossterm::{
-    terminal::{disable_raw_mode, enable_raw_mode},
+use crossterm::event::{KeyCode, KeyModifiers};
+
+use tokio::sync::mpsc;
+use std::{
+    env,
+    io,
+    process,
+    sync::Arc,
 };
 
-use thiserror::Error;
-use std::io;
-
-#[derive(Error, Debug)]
-pub enum Error {
-    #[error("error reading the DB file: {0}")]
-    ReadDBError(#[from] io::Error),
-    #[error("error parsing the DB file: {0}")]
-    ParseDBError(#[from] serde_json::Error),
+#[tokio::main]
+async fn main() {
+    if let Err(err) = run().await {
+        eprintln!("error: {}", err);
+        if let error::Error::Usage(_) = err {
+            eprintln!("\n{}", cli::USAGE);
+        }
+        std::process::exit(1);
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    println!("mark as read ... start");
+async fn run() -> Result<()> {
+    let command = cli::parse(env::args().skip(1))?;
+    if command == Command::Help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
 
-    // APIクライアント初期化
     let mut config = Config::new();
-    config.init();
-    let client = GClient::new(&config.valid_token.as_ref().unwrap());
-    let message_client = MessageClient::new(&client);
-
-    // 未読リスト取得
-    let unread_message_list = message_client.get_unread_list().await?;
-    let unread_num = unread_message_list.len();
-
-    if unread_num == 0 {
-        println!("no unread ... end");
-        std::process::exit(0);
-    } else {
-        println!("unread count is {}", unread_num);
+    config.init()?;
+
+    // 終了するまで他のプロセスからは開けない
+    let mut store = Store::open(&config)?;
+
+    // Targetリストの編集や履歴の表示だけならAPIは不要
+    if !command.needs_backend() {
+        return match command {
+            Command::Journal => cli::journal(&store),
+            _ => cli::targets(&mut store, &command),
+        };
     }
 
-    // 未読リストの詳細データを埋める
-    let filled_message_list = message_client.fill_messages_metadata(&unread_message_list).await?;
+    eprintln!("mark as read ... start");
+
+    // APIクライアント初期化
+    let mail_backend = connect(&mut config).await?;
 
-    // 表示用にアドレスと数値のリストを生成
-    let address_count_list = message::get_address_count_list(
-        &filled_message_list
-    ).unwrap();
-    // FIXME: 関数内で生まれた値は一度外だししないと、参照にできないのが不便？
-    let (address_list, count_list) = message::split_address_count(&address_count_list);
+    // 取り消しは未読リストを使わない
+    if let Command::Undo(index) = command {
+        return cli::undo(&mut store, mail_backend.as_ref(), index).await;
+    }
+
+    // 前回取得したメタデータ（読めなければ取得し直す）
+    let cache = MetadataCache::load(config.cache_path).unwrap_or_default();
+
+    // TUIはキャッシュの内容ですぐに表示し、未読の取得は裏で行う
+    if command == Command::Tui {
+        return run_tui(&config, store, mail_backend, cache).await;
+    }
+
+    // 未読リストとその詳細データ
+    let cache = fetch_unread_messages(&config, mail_backend.as_ref(), &cache).await?;
+    cache.save(config.cache_path)?;
+    let message_list = cache.messages();
+
+    match command {
+        Command::List => cli::list(&message_list),
+        Command::Mark { from, dry_run } => cli::mark(&config, &mut store, mail_backend.as_ref(), &message_list, &from, dry_run).await?,
+        Command::Run { dry_run } => cli::run(&config, &mut store, mail_backend.as_ref(), &message_list, dry_run).await?,
+        _ => {},
+    }
+
+    Ok(())
+}
 
+async fn connect(config: &mut Config<'_>) -> Result<Arc<dyn MailBackend>> {
+    let mail_backend: Arc<dyn MailBackend> = match config.backend {
+        BackendKind::Gmail => {
+            config.set_token()?;
+            let client = GClient::new(
+                config.valid_token.clone().unwrap_or_default(),
+                config.token_path,
+                config.endpoints.clone(),
+                config.quota_per_second,
+            )?;
+            client.authorize().await?;
+            Arc::new(MessageClient::new(client, &config.api_base_url))
+        },
+        BackendKind::Fake => {
+            Arc::new(FakeBackend::load(config.fake_mailbox_path)?)
+        }
+    };
+
+    Ok(mail_backend)
+}
+
+// 前回からの変更を反映した未読リストとそのメタデータ
+// 進捗は標準出力を汚さないよう標準エラーに出す
+async fn fetch_unread_messages(
+    config: &Config<'_>,
+    mail_backend: &dyn MailBackend,
+    cache: &MetadataCache,
+) -> Result<MetadataCache> {
+    let synced = backend::sync_unread(
+        mail_backend,
+        config.max_results,
+        config.unread_limit,
+        config.concurrency,
+        cache,
+        |_, count| eprintln!("fetching unread list ... {}", count),
+    ).await?;
+    if !synced.failed.is_empty() {
+        eprintln!("{}", backend::describe_failed(&synced.failed));
+    }
+    eprintln!("unread count is {}", synced.cache.messages().len());
+
+    Ok(synced.cache)
+}
+
+async fn run_tui(
+    config: &Config<'_>,
+    store: Store<'_>,
+    mail_backend: Arc<dyn MailBackend>,
+    cache: MetadataCache,
+) -> Result<()> {
     // rowモード
-    enable_raw_mode().expect("raw mode");
+    // guardがdropされるとき（エラー・panic含む）に画面を元に戻す
+    let guard = TerminalGuard::new()?;
+    let mut signals = terminal::spawn_signal_handler();
+    // 終了の指示を受けたときの終了コード
+    // Execute中は実行中のチャンクの結果を記録してから抜ける（もう一度指示されたらすぐ抜ける）
+    let mut quitting: Option<i32> = None;
 
-    let events = Events::new(200);
+    let mut events = Events::new(200);
+    // 裏で動かしたAPI呼び出しの結果
+    let (task_tx, mut task_rx) = mpsc::unbounded_channel();
 
     // 画面初期化
     let stdout = io::stdout();
@@ -75,11 +160,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     terminal.clear()?;
 
     let mut app = App::new(
-        &config,
-        &filled_message_list,
-        &address_list,
-        &count_list,
+        config,
+        store,
+        mail_backend,
+        cache,
+        task_tx,
     );
+    app.start_refresh();
 
     terminal.clear()?;
 
@@ -89,27 +176,68 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             app.draw(f);
         })?;
 
-        match events.next()? {
-            Event::Input(event) => {
-                match app.event(event.code) {
-                    Ok(state) => {
-                        if state == EventState::NotConsumed {
-                            break;
-                        }
-                    },
-                    Err(_err) => {
+        // キー入力・tick・API呼び出しの結果のうち先に来たものを処理する
+        // API呼び出しは裏で動くので待っている間も描画が止まらない
+        tokio::select! {
+            event = events.next() => match event {
+                // rawモードではCtrl-CがSIGINTにならないのでキー入力として受ける
+                Some(Event::Input(event)) if event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    if quitting.replace(0).is_some() {
                         break;
                     }
+                    app.interrupt_execution();
+                },
+                Some(Event::Input(event)) => {
+                    match app.event(event.code) {
+                        Ok(state) => {
+                            if state == EventState::NotConsumed {
+                                if quitting.replace(0).is_some() {
+                                    break;
+                                }
+                                app.interrupt_execution();
+                            }
+                        },
+                        Err(err) => {
+                            app.report_error(&err);
+                        }
+                    }
+                },
+                Some(Event::Tick) => {
+                    app.tick();
+                },
+                None => break,
+            },
+            Some(result) = task_rx.recv() => {
+                if let Err(err) = app.handle_task(result) {
+                    app.report_error(&err);
                 }
             },
-            Event::Tick => {
-                // 次のループへ
-            }
+            Some(code) = signals.recv() => {
+                if quitting.replace(code).is_some() {
+                    break;
+                }
+                app.interrupt_execution();
+            },
+        }
+
+        if quitting.is_some() && !app.is_executing() {
+            break;
         }
     }
 
-    disable_raw_mode()?;
-    terminal.show_cursor()?;
+    // 既読にしたものを除いて次回の起動に使う
+    let result = app.save_cache();
 
-    Ok(())
+    // シグナルで終了したときは 128 + シグナル番号 で終わる
+    match quitting {
+        Some(code) if code != 0 => {
+            drop(app);
+            drop(guard);
+            if let Err(err) = result {
+                eprintln!("{}", err);
+            }
+            process::exit(code);
+        },
+        _ => result,
+    }
 }
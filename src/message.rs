@@ -1,156 +1,390 @@
-use crate::request::client::{
-    GClient,
-    Method
+use crate::request::{
+    batch,
+    client::{GClient, Method},
 };
-use reqwest::Error;
-use futures::future::join_all;
+use crate::error::Result;
+use crate::rule::Rule;
+use crate::sender::{self, Sender};
+use chrono::{DateTime, TimeZone, Utc};
 
 extern crate serde;
 extern crate serde_json;
 use serde::{Serialize, Deserialize};
-use serde_json::Value;
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
 };
 
-pub struct MessageClient<'a> {
-    pub client: &'a GClient,
-    pub message: Option<Message>,
+// batchModifyで一度に指定できるIDの上限
+pub const BATCH_MODIFY_LIMIT: usize = 1000;
+
+// メソッドごとに消費するクォータ単位
+// https://developers.google.com/gmail/api/reference/quota
+const QUOTA_MESSAGES_LIST: u32 = 5;
+const QUOTA_MESSAGES_GET: u32 = 5;
+const QUOTA_GET_PROFILE: u32 = 1;
+const QUOTA_HISTORY_LIST: u32 = 2;
+const QUOTA_BATCH_MODIFY: u32 = 50;
+
+// バッチリクエスト1回にまとめられる呼び出しの上限
+pub const METADATA_BATCH_LIMIT: usize = 100;
+
+// metadataHeaders: From, Subject, Date に絞ったメタデータとsnippet
+const METADATA_QUERY: [(&str, &str); 5] = [
+    ("format", "metadata"),
+    ("metadataHeaders", "From"),
+    ("metadataHeaders", "Subject"),
+    ("metadataHeaders", "Date"),
+    ("fields", "id,threadId,labelIds,historyId,internalDate,snippet,payload/headers"),
+];
+
+// Gmail REST APIのクライアント
+pub struct MessageClient {
+    pub client: GClient,
+    // https://gmail.googleapis.com など、パスを除いた接続先
+    pub base_url: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Message {
     pub id: Option<String>,
     pub thread_id: Option<String>,
+    pub label_ids: Option<Vec<String>>,
+    // 最後に変更されたときの履歴ID
+    pub history_id: Option<String>,
+    // 受信日時（エポックミリ秒の文字列）
+    pub internal_date: Option<String>,
+    // 本文の冒頭（HTMLエスケープされている）
+    pub snippet: Option<String>,
     pub payload: Option<Payload>,
 }
 
+// users/me/messages のレスポンス
+// 未読が0件の場合は messages 自体が返ってこない
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageListPage {
+    pub messages: Option<Vec<Message>>,
+    pub next_page_token: Option<String>,
+    pub result_size_estimate: Option<u32>,
+}
+
+// users/me/profile のレスポンス
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub email_address: Option<String>,
+    // メールボックスの現在の履歴ID
+    pub history_id: Option<String>,
+}
+
+// users/me/history のレスポンス
+// 変更がなければ history 自体が返ってこない
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryPage {
+    pub history: Option<Vec<History>>,
+    pub next_page_token: Option<String>,
+    pub history_id: Option<String>,
+}
+
+// 1回分の変更
+// messageにはIDと変更後のlabelIdsだけが入っている
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct History {
+    pub id: Option<String>,
+    #[serde(default)]
+    pub messages_added: Vec<HistoryMessage>,
+    #[serde(default)]
+    pub messages_deleted: Vec<HistoryMessage>,
+    #[serde(default)]
+    pub labels_added: Vec<HistoryMessage>,
+    #[serde(default)]
+    pub labels_removed: Vec<HistoryMessage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryMessage {
+    pub message: Message,
+    // labelsAdded・labelsRemovedで付け外しされたラベル
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub label_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct Payload {
     pub headers: Option<Vec<Header>>,
 }
 
-#[derive(PartialEq, Serialize, Deserialize, Debug)]
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Header {
     pub name: Option<String>,
     pub value: Option<String>,
 }
 
-impl<'a> MessageClient<'a> {
-    pub fn new(client: &'a GClient) -> Self {
+impl MessageClient {
+    pub fn new(client: GClient, base_url: &str) -> Self {
         Self {
             client,
-            message: None,
+            base_url: base_url.trim_end_matches('/').to_string(),
         }
     }
 
-    // 未読メッセージのリストを取得
-    pub async fn get_unread_list(&self) -> Result<Vec<Message>, Error> {
+    fn user_url(&self, path: &str) -> String {
+        format!("{}/gmail/v1/users/me{}", self.base_url, path)
+    }
+
+    fn messages_url(&self, path: &str) -> String {
+        self.user_url(&format!("/messages{}", path))
+    }
+
+    // 未読メッセージのリストを1ページ分取得
+    pub async fn get_unread_page(&self, page_token: Option<&str>, max_results: u32) -> Result<MessageListPage> {
+        let max_results = max_results.to_string();
+        let mut query = vec![
+            ("q", "is:unread"),
+            ("maxResults", max_results.as_str()),
+        ];
+        if let Some(page_token) = page_token {
+            query.push(("pageToken", page_token));
+        }
+
         let res_body = self.client.call_api(
-            &"https://gmail.googleapis.com/gmail/v1/users/me/messages",
-            &vec![("q", "is:unread")],
+            &self.messages_url(""),
+            &query,
             &HashMap::new(),
             Method::GET,
+            QUOTA_MESSAGES_LIST,
         ).await?;
 
-        let v: Value = serde_json::from_str(res_body.as_ref().unwrap()).unwrap();
-        let v_m = v["messages"].as_array().unwrap();
+        let page: MessageListPage = serde_json::from_str(&res_body.unwrap_or_default())?;
 
-        let messages = v_m.to_vec().into_iter().map(|m| {
-            serde_json::from_value(m).unwrap()
-        }).collect::<Vec<Message>>();
+        Ok(page)
+    }
 
-        Ok(messages)
+    // メールボックスの情報（現在の履歴IDなど）
+    pub async fn get_profile(&self) -> Result<Profile> {
+        let res_body = self.client.call_api(
+            &self.user_url("/profile"),
+            &vec![],
+            &HashMap::new(),
+            Method::GET,
+            QUOTA_GET_PROFILE,
+        ).await?;
+
+        Ok(serde_json::from_str(&res_body.unwrap_or_default())?)
     }
 
-    // メッセージのメタデータを埋める
-    pub async fn fill_messages_metadata(&self, message_list: &Vec<Message>) -> Result<Vec<Message>, Error> {
-        let res_list = join_all(
-            message_list.iter().map(|m| {
-                self.get_metadata_from_only(m)
-            })
-        ).await;
+    // 履歴IDより後のメッセージの追加・削除とラベルの変更を1ページ分取得
+    // 履歴IDが古すぎる場合は404が返る
+    pub async fn get_history_page(&self, start_history_id: &str, page_token: Option<&str>) -> Result<HistoryPage> {
+        let mut query = vec![
+            ("startHistoryId", start_history_id),
+            ("historyTypes", "messageAdded"),
+            ("historyTypes", "messageDeleted"),
+            ("historyTypes", "labelAdded"),
+            ("historyTypes", "labelRemoved"),
+            ("maxResults", "500"),
+        ];
+        if let Some(page_token) = page_token {
+            query.push(("pageToken", page_token));
+        }
 
-        let filled = res_list.into_iter().map(
-            |meta| {
-                let m: Message = serde_json::from_str(&meta.unwrap()).unwrap();
-                m
-            }
-        )
-            // .inspect(|m| println!("{:?}", m))
-            .collect::<Vec<Message>>();
+        let res_body = self.client.call_api(
+            &self.user_url("/history"),
+            &query,
+            &HashMap::new(),
+            Method::GET,
+            QUOTA_HISTORY_LIST,
+        ).await?;
 
-        Ok(filled)
+        Ok(serde_json::from_str(&res_body.unwrap_or_default())?)
     }
 
-    // metadataHeaders: From に絞ったメタデータを取得
-    async fn get_metadata_from_only(&self, message: &Message) -> Result<String, Error> {
-        let id = message.id.as_ref().unwrap();
-        let url =
-            "https://gmail.googleapis.com/gmail/v1/users/me/messages/".to_string() + id;
+    // メタデータとsnippetを取得
+    pub async fn get_metadata_headers(&self, id: &str) -> Result<String> {
+        let url = self.messages_url(&format!("/{}", id));
 
         let res_body = self.client.call_api(
             &url,
-            &vec![
-                ("format", "metadata"),
-                ("metadataHeaders", "From"),
-            ],
+            &METADATA_QUERY.to_vec(),
             &HashMap::new(),
             Method::GET,
+            QUOTA_MESSAGES_GET,
+        ).await?;
+
+        Ok(res_body.unwrap_or_default())
+    }
+
+    // 最大METADATA_BATCH_LIMIT件のメタデータをバッチリクエスト1回で取得
+    // 結果はIDと同じ順で、失敗したものはそのパートのエラー
+    pub async fn get_metadata_headers_batch(&self, ids: &[String]) -> Result<Vec<Result<String>>> {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(METADATA_QUERY.iter())
+            .finish();
+        let requests = ids.iter()
+            .map(|id| (id.as_str(), format!("/gmail/v1/users/me/messages/{}?{}", id, query)))
+            .collect::<Vec<(&str, String)>>();
+
+        let url = format!("{}/batch/gmail/v1", self.base_url);
+        // クォータはまとめた呼び出しの分だけ消費する
+        let (content_type, res_body) = self.client.call_batch(
+            &url,
+            &batch::build_body(&requests),
+            QUOTA_MESSAGES_GET * ids.len() as u32,
         ).await?;
 
-        Ok(res_body.unwrap())
+        let parts = batch::parse_response(&content_type, &res_body)?;
+
+        Ok(batch::match_parts(ids, parts))
     }
 
-    // 既読化
-    async fn post_remove_unread(&self, ids: Vec<&str>) -> Result<(), Error> {
+    // ラベルの一括付け外し（既読化はUNREADを外す）
+    pub async fn post_modify_labels(&self, ids: &[&str], add: &[&str], remove: &[&str]) -> Result<()> {
         let mut req_body = HashMap::new();
-        req_body.insert("ids", ids);
-        req_body.insert("removeLabelIds", vec!["UNREAD"]);
+        req_body.insert("ids", ids.to_vec());
+        if !add.is_empty() {
+            req_body.insert("addLabelIds", add.to_vec());
+        }
+        if !remove.is_empty() {
+            req_body.insert("removeLabelIds", remove.to_vec());
+        }
 
-        let url = "https://gmail.googleapis.com/gmail/v1/users/me/messages/batchModify";
+        let url = self.messages_url("/batchModify");
 
         let _ = self.client.call_api(
             &url,
             &vec![],
             &req_body,
             Method::POST,
+            QUOTA_BATCH_MODIFY,
         ).await?;
 
         Ok(())
     }
 }
 
-pub fn get_address_count_list(list: &Vec<Message>) -> Result<HashMap<&str, String>, ()> {
-    let mut address_count_list = HashMap::new();
-    let mut tmp_address_count_list = HashMap::new();
+// 未読リストに載せないラベル（q=is:unread の検索にも出てこない）
+pub const EXCLUDED_LABELS: [&str; 2] = ["SPAM", "TRASH"];
+
+impl Message {
+    // 未読リストに載るもの（UNREADが付いていて、迷惑メール・ゴミ箱にないもの）
+    pub fn is_listed_unread(&self) -> bool {
+        let labels = match &self.label_ids {
+            Some(labels) => labels,
+            None => return false,
+        };
+        labels.iter().any(|l| l == "UNREAD") && !labels.iter().any(|l| EXCLUDED_LABELS.contains(&l.as_str()))
+    }
+
+    // 指定したヘッダーの値
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.payload.as_ref()?.headers.as_ref()?
+            .iter()
+            .find(|h| h.name.as_deref() == Some(name))?
+            .value.as_deref()
+    }
 
-    for m in list.iter() {
-        let headers = m.payload.as_ref().unwrap().headers.as_ref().unwrap();
-        // タイトルだけしかないはず
-        if headers.len() > 1 {
-            return Err(()); // FIXME 自作エラーを学ぶ
+    // Fromヘッダーの送信者
+    pub fn sender(&self) -> Option<Sender> {
+        self.header("From").map(Sender::parse)
+    }
+
+    // encoded-wordをデコードした件名
+    pub fn subject(&self) -> Option<String> {
+        self.header("Subject").map(sender::decode_header)
+    }
+
+    // 表示用にHTMLエスケープを戻したsnippet
+    pub fn snippet_text(&self) -> Option<String> {
+        let snippet = self.snippet.as_ref()?;
+
+        Some(snippet
+            .replace("&quot;", "\"")
+            .replace("&#39;", "'")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"))
+    }
+
+    // 受信日時
+    // internalDateがなければDateヘッダーを使う
+    pub fn received_at(&self) -> Option<DateTime<Utc>> {
+        if let Some(millis) = self.internal_date.as_ref().and_then(|d| d.parse::<i64>().ok()) {
+            return Utc.timestamp_millis_opt(millis).single();
         }
-        let address = headers[0].value.as_ref().unwrap().as_str();
 
-        let count = tmp_address_count_list.entry(address).or_insert(0);
-        *count += 1;
+        DateTime::parse_from_rfc2822(self.header("Date")?)
+            .ok()
+            .map(|date| date.with_timezone(&Utc))
     }
+}
 
-    for element in tmp_address_count_list {
-        address_count_list.insert(element.0, element.1.to_string());
+// 送信者ごとの未読数と最新の受信日時
+#[derive(Debug, Clone)]
+pub struct SenderSummary {
+    pub sender: Sender,
+    pub count: usize,
+    pub newest: Option<DateTime<Utc>>,
+}
+
+// 送信者ごとの未読数（多い順）
+// アドレスでまとめ、表示名は最初に見つかったものを使う
+pub fn get_sender_count_list(list: &Vec<Message>) -> Vec<SenderSummary> {
+    let mut index_list: HashMap<String, usize> = HashMap::new();
+    let mut sender_count_list: Vec<SenderSummary> = Vec::new();
+
+    // Fromヘッダーがないメッセージは数えない
+    for message in list {
+        let sender = match message.sender() {
+            Some(sender) => sender,
+            None => continue,
+        };
+        let received_at = message.received_at();
+
+        match index_list.get(&sender.address) {
+            Some(&i) => {
+                let summary = &mut sender_count_list[i];
+                summary.count += 1;
+                summary.newest = summary.newest.max(received_at);
+            },
+            None => {
+                index_list.insert(sender.address.clone(), sender_count_list.len());
+                sender_count_list.push(SenderSummary {
+                    sender,
+                    count: 1,
+                    newest: received_at,
+                });
+            }
+        }
     }
 
-    Ok(address_count_list)
+    sender_count_list.sort_by(|a, b| b.count.cmp(&a.count).then(a.sender.address.cmp(&b.sender.address)));
+    sender_count_list
 }
 
-pub fn split_address_count<'a>(list: &'a HashMap<&str, String>) -> (Vec<&'a str>, Vec<&'a str>) {
-    let address_list = list.keys().map(|k| *k).collect::<Vec<&str>>();
-    let count_list = list.values().map(|v| v.as_str()).collect::<Vec<&str>>();
+// ルールごとに一致するメッセージIDを集める
+// 複数のルールに一致したメッセージは最初のルールにだけ数える
+pub fn collect_targets(list: &Vec<Message>, rules: &Vec<Rule>) -> Result<Vec<(String, Vec<String>)>> {
+    let mut collected: HashSet<&str> = HashSet::new();
+    let mut targets = Vec::new();
+
+    for rule in rules {
+        let matcher = rule.matcher()?;
+        let id_list = list.iter()
+            .filter(|m| matcher.matches(m))
+            .filter_map(|m| m.id.as_deref())
+            .filter(|id| !collected.contains(id))
+            .collect::<Vec<&str>>();
+
+        collected.extend(&id_list);
+        targets.push((rule.to_string(), id_list.iter().map(|id| id.to_string()).collect()));
+    }
 
-    (address_list, count_list)
+    Ok(targets)
 }
@@ -0,0 +1,54 @@
+use crate::error::{Error, Result};
+use crate::message::Message;
+use chrono::Local;
+use std::collections::HashSet;
+use std::fs;
+
+// Dry-runで既読化されるはずだったメッセージの一覧
+pub struct DryRunReport {
+    pub per_target: Vec<(String, usize)>,
+    pub total: usize,
+    lines: Vec<String>,
+}
+
+impl DryRunReport {
+    pub fn new(message_list: &Vec<Message>, targets: &Vec<(String, Vec<String>)>) -> Self {
+        let mut lines = vec![format!("dry-run report {}", Local::now().format("%Y-%m-%d %H:%M:%S"))];
+        let mut per_target = Vec::new();
+        let mut total = 0;
+
+        for (target, ids) in targets {
+            lines.push(format!("target: {} ({} messages)", target, ids.len()));
+
+            let id_set: HashSet<&str> = ids.iter().map(String::as_str).collect();
+            for message in message_list.iter().filter(|m| m.id.as_deref().is_some_and(|id| id_set.contains(id))) {
+                lines.push(format!(
+                    "    {}  {}  [{}]",
+                    message.header("Date").unwrap_or("-"),
+                    message.subject().unwrap_or_else(|| "(no subject)".to_string()),
+                    message.sender().map_or("-".to_string(), |s| s.to_string()),
+                ));
+            }
+
+            per_target.push((target.clone(), ids.len()));
+            total += ids.len();
+        }
+
+        lines.push(format!("total: {} messages", total));
+
+        Self {
+            per_target,
+            total,
+            lines,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        self.lines.join("\n") + "\n"
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_text())
+            .map_err(|source| Error::File { path: path.to_string(), source })
+    }
+}
@@ -0,0 +1,193 @@
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+
+// バッチリクエストのパートの区切り
+pub const BOUNDARY: &str = "batch_mark_as_read";
+
+// バッチのレスポンスの1パート分
+#[derive(Debug)]
+pub struct BatchPart {
+    // リクエストに付けたContent-ID
+    pub content_id: Option<String>,
+    pub status: u16,
+    pub body: String,
+}
+
+// multipart/mixed のバッチリクエストのボディ
+// requestsは (Content-ID, ホストを除いたパスとクエリ) のGETリクエスト
+pub fn build_body(requests: &[(&str, String)]) -> String {
+    let mut body = String::new();
+    for (content_id, path) in requests {
+        body += &format!("--{}\r\n", BOUNDARY);
+        body += "Content-Type: application/http\r\n";
+        body += &format!("Content-ID: <{}>\r\n\r\n", content_id);
+        body += &format!("GET {}\r\n\r\n", path);
+    }
+    body += &format!("--{}--\r\n", BOUNDARY);
+
+    body
+}
+
+// バッチのレスポンスをパートごとに分ける
+// Content-TypeのboundaryはGmail側で決まる
+pub fn parse_response(content_type: &str, body: &str) -> Result<Vec<BatchPart>> {
+    let boundary = content_type.split(';')
+        .map(|param| param.trim())
+        .find_map(|param| param.strip_prefix("boundary="))
+        .map(|boundary| boundary.trim_matches('"'))
+        .ok_or_else(|| Error::Batch(format!("no boundary in {}", content_type)))?;
+    let delimiter = format!("--{}", boundary);
+
+    let mut parts = Vec::new();
+    // 最初の区切りより前は読み飛ばし、"--boundary--" で終わり
+    for part in body.split(delimiter.as_str()).skip(1) {
+        if part.starts_with("--") {
+            break;
+        }
+        parts.push(parse_part(part)?);
+    }
+
+    Ok(parts)
+}
+
+// Content-IDでリクエストと対応付け、idsの順にパートごとの結果を返す
+pub fn match_parts(ids: &[String], parts: Vec<BatchPart>) -> Vec<Result<String>> {
+    let mut parts = parts.into_iter()
+        .filter_map(|part| part.content_id.clone().map(|id| (id, part)))
+        .collect::<HashMap<String, BatchPart>>();
+
+    ids.iter().map(|id| match parts.remove(id) {
+        Some(part) if (200..300).contains(&part.status) => Ok(part.body),
+        Some(part) => Err(Error::from_response(part.status, &format!("batch part {}", id), &part.body)),
+        None => Err(Error::Batch(format!("no response for {}", id))),
+    }).collect()
+}
+
+// パートのヘッダーの後にHTTPレスポンスがそのまま入っている
+fn parse_part(part: &str) -> Result<BatchPart> {
+    let (part_head, response) = split_head(part.trim_start());
+    let content_id = headers(part_head).remove("content-id")
+        .map(|id| id.trim_matches(|c| c == '<' || c == '>').to_string())
+        // レスポンスでは "response-" が前に付く
+        .map(|id| id.strip_prefix("response-").map(|id| id.to_string()).unwrap_or(id));
+
+    let (response_head, response_body) = split_head(response);
+    let status = response_head.lines().next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| Error::Batch(format!("no status line in part {}", content_id.as_deref().unwrap_or("?"))))?;
+
+    Ok(BatchPart {
+        content_id,
+        status,
+        body: response_body.trim_end().to_string(),
+    })
+}
+
+// 空行でヘッダーとボディに分ける
+fn split_head(text: &str) -> (&str, &str) {
+    if let Some(i) = text.find("\r\n\r\n") {
+        return (&text[..i], &text[i + 4..]);
+    }
+    match text.find("\n\n") {
+        Some(i) => (&text[..i], &text[i + 2..]),
+        None => (text, ""),
+    }
+}
+
+// ヘッダー名は小文字にそろえる
+fn headers(head: &str) -> HashMap<String, String> {
+    head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(list: &[&str]) -> Vec<String> {
+        list.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn build_body_has_a_part_per_request() {
+        let body = build_body(&[("a", "/gmail/v1/users/me/messages/a".to_string())]);
+
+        assert_eq!(body, "--batch_mark_as_read\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: <a>\r\n\r\n\
+            GET /gmail/v1/users/me/messages/a\r\n\r\n\
+            --batch_mark_as_read--\r\n");
+    }
+
+    #[test]
+    fn parses_quoted_boundary() {
+        let body = "--batch_x\r\n\
+            Content-Type: application/http\r\n\
+            Content-ID: <response-a>\r\n\r\n\
+            HTTP/1.1 200 OK\r\n\
+            Content-Type: application/json; charset=UTF-8\r\n\r\n\
+            {\"id\":\"a\"}\r\n\
+            --batch_x--\r\n";
+
+        let parts = parse_response("multipart/mixed; boundary=\"batch_x\"", body).unwrap();
+
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].content_id.as_deref(), Some("a"));
+        assert_eq!(parts[0].status, 200);
+        assert_eq!(parts[0].body, "{\"id\":\"a\"}");
+    }
+
+    #[test]
+    fn parses_lf_only_line_endings() {
+        let body = "preamble\n--b\nContent-ID: <response-a>\n\nHTTP/1.1 200 OK\n\n{}\n\
+            --b\nContent-ID: <response-b>\n\nHTTP/1.1 200 OK\n\n[]\n--b--\n";
+
+        let parts = parse_response("multipart/mixed; boundary=b", body).unwrap();
+
+        let bodies = parts.iter().map(|p| (p.content_id.as_deref().unwrap(), p.body.as_str())).collect::<Vec<_>>();
+        assert_eq!(bodies, vec![("a", "{}"), ("b", "[]")]);
+    }
+
+    #[test]
+    fn missing_boundary_is_an_error() {
+        assert!(parse_response("multipart/mixed", "").is_err());
+    }
+
+    #[test]
+    fn missing_part_is_an_error_for_that_id_only() {
+        let body = "--b\r\nContent-ID: <response-a>\r\n\r\nHTTP/1.1 200 OK\r\n\r\n{}\r\n--b--";
+        let parts = parse_response("multipart/mixed; boundary=b", body).unwrap();
+
+        let results = match_parts(&ids(&["a", "b"]), parts);
+
+        assert_eq!(results[0].as_deref().unwrap(), "{}");
+        assert!(matches!(results[1], Err(Error::Batch(_))));
+    }
+
+    #[test]
+    fn non_2xx_part_becomes_its_own_error() {
+        let body = "--b\r\nContent-ID: <response-a>\r\n\r\n\
+            HTTP/1.1 404 Not Found\r\n\r\n\
+            {\"error\": {\"code\": 404, \"message\": \"Requested entity was not found.\", \"status\": \"NOT_FOUND\"}}\r\n\
+            --b\r\nContent-ID: <response-b>\r\n\r\n\
+            HTTP/1.1 429 Too Many Requests\r\n\r\n\
+            {\"error\": {\"code\": 429, \"message\": \"Too many\", \"errors\": [{\"reason\": \"rateLimitExceeded\"}]}}\r\n\
+            --b\r\nContent-ID: <response-c>\r\n\r\nHTTP/1.1 200 OK\r\n\r\n{}\r\n--b--";
+        let parts = parse_response("multipart/mixed; boundary=b", body).unwrap();
+
+        let results = match_parts(&ids(&["a", "b", "c"]), parts);
+
+        assert!(results[0].as_ref().unwrap_err().is_not_found());
+        assert!(results[1].as_ref().unwrap_err().is_retryable());
+        assert!(results[2].is_ok());
+    }
+
+    #[test]
+    fn part_without_status_line_is_an_error() {
+        let body = "--b\r\nContent-ID: <response-a>\r\n\r\n\r\n--b--";
+        assert!(parse_response("multipart/mixed; boundary=b", body).is_err());
+    }
+}
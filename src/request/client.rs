@@ -1,12 +1,29 @@
-use reqwest::{Client, Error, header::*};
-use std::collections::HashMap;
+use super::{
+    batch::BOUNDARY,
+    rate_limit::RateLimiter,
+    secret::{self, Endpoints},
+    token::Token,
+};
+use crate::error::{Error, Result};
+use rand::Rng;
+use reqwest::{Client, RequestBuilder, Response, StatusCode, header::*};
+use tokio::{sync::Mutex, time::delay_for};
+use std::{collections::HashMap, time::Duration};
+
+// クォータ超過・サーバー側のエラーで再送する回数
+const MAX_RETRIES: u32 = 6;
 
 // reqwest client wrapper
 #[derive(Debug)]
 pub struct GClient {
     pub client: Client,
+    token: Mutex<Token>,
+    token_path: String,
+    endpoints: Endpoints,
+    rate_limiter: RateLimiter,
 }
 
+#[derive(Clone, Copy)]
 pub enum Method {
     GET,
     POST,
@@ -15,62 +32,203 @@ pub enum Method {
 impl Default for GClient {
     fn default() -> Self {
         Self {
-            client: Client::new()
+            client: Client::new(),
+            token: Mutex::new(Token::default()),
+            token_path: String::new(),
+            endpoints: Endpoints::default(),
+            rate_limiter: RateLimiter::default(),
         }
     }
 }
 
 impl GClient {
-    pub fn new(token: &str) -> Self {
-        let client = Client::builder().default_headers(Self::gen_headers(token)).build().unwrap();
-        GClient {
-            client
-        }
+    // quota_per_secondは1秒あたりに使うクォータ単位の上限
+    pub fn new(token: Token, token_path: &str, endpoints: Endpoints, quota_per_second: u32) -> Result<Self> {
+        let client = Client::builder().default_headers(Self::gen_headers()).build()?;
+        Ok(GClient {
+            client,
+            token: Mutex::new(token),
+            token_path: token_path.to_string(),
+            endpoints,
+            rate_limiter: RateLimiter::new(quota_per_second),
+        })
     }
 
-    pub fn gen_headers(token: &str) -> HeaderMap {
+    pub fn gen_headers() -> HeaderMap {
         let mut req_headers = HeaderMap::new();
         req_headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         req_headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
 
-        match HeaderValue::from_str(&("Bearer ".to_string() + token)) {
-            Ok(auth) => {
-                req_headers.insert(AUTHORIZATION, auth);
+        req_headers
+    }
+
+    // 起動時のトークン確認
+    // リフレッシュトークンが取り消されていたらブラウザで認可し直す
+    pub async fn authorize(&self) -> Result<()> {
+        let mut token = self.token.lock().await;
+        if !token.is_expired() {
+            return Ok(());
+        }
+
+        let renewed = match secret::refresh_access_token(&self.client, &self.endpoints, &token).await {
+            Ok(renewed) => renewed,
+            Err(Error::TokenRevoked) => {
+                println!("oauth2 token is revoked ... re-authorize");
+                secret::get_oauth2_token(&self.endpoints)?
             },
-            Err(_) => {
-                unreachable!();
-            }
+            Err(err) => return Err(err),
         };
 
-        req_headers
+        renewed.save(&self.token_path)?;
+        *token = renewed;
+
+        Ok(())
+    }
+
+    // 期限切れ間近なら更新してからアクセストークンを返す
+    async fn access_token(&self) -> Result<String> {
+        let mut token = self.token.lock().await;
+        if token.is_expired() {
+            self.refresh(&mut token).await?;
+        }
+
+        Ok(token.access_token.clone())
+    }
+
+    // 401が返ったときの更新
+    // 他のリクエストが先に更新済みなら何もしない
+    async fn refresh_if_unchanged(&self, used: &str) -> Result<()> {
+        let mut token = self.token.lock().await;
+        if token.access_token == used {
+            self.refresh(&mut token).await?;
+        }
+
+        Ok(())
+    }
+
+    // 使用中にリフレッシュトークンが取り消されたら、次の起動で認可し直すよう保存したものを消してエラーにする
+    // （起動中はTUIが画面を使っているのでブラウザでの認可はできない）
+    async fn refresh(&self, token: &mut Token) -> Result<()> {
+        let renewed = match secret::refresh_access_token(&self.client, &self.endpoints, token).await {
+            Ok(renewed) => renewed,
+            Err(Error::TokenRevoked) => {
+                token.refresh_token = None;
+                let _ = token.save(&self.token_path);
+                return Err(Error::TokenRevoked);
+            },
+            Err(err) => return Err(err),
+        };
+
+        let _ = renewed.save(&self.token_path);
+        *token = renewed;
+
+        Ok(())
+    }
+
+    fn build_request(
+        &self,
+        url: &str,
+        query: &Vec<(&str, &str)>,
+        body: &HashMap<&str, Vec<&str>>,
+        method: Method,
+        access_token: &str,
+    ) -> RequestBuilder
+    {
+        let req = match method {
+            Method::GET => self.client.get(url).query(query),
+            Method::POST => self.client.post(url).json(body),
+        };
+
+        req.bearer_auth(access_token)
     }
 
+    // quota_unitsはこの呼び出しで消費するGmail APIのクォータ単位
     pub async fn call_api(
         &self,
         url: &str,
         query: &Vec<(&str, &str)>,
         body: &HashMap<&str, Vec<&str>>,
         method: Method,
-    ) -> Result<Option<String>, Error>
+        quota_units: u32,
+    ) -> Result<Option<String>>
     {
+        let (_, res_body) = self.execute(url, quota_units, |access_token| {
+            self.build_request(url, query, body, method, access_token)
+        }).await?;
+
         match method {
-            Method::GET => {
-                let res_body = self.client
-                    .get(url)
-                    .query(query)
-                    .send().await?
-                    .text().await?;
-
-                Ok(Some(res_body))
-            },
-            Method::POST => {
-                let _ = self.client
-                    .post(url)
-                    .json(body)
-                    .send().await?;
+            Method::GET => Ok(Some(res_body)),
+            Method::POST => Ok(None),
+        }
+    }
 
-                Ok(None)
+    // multipart/mixed のバッチリクエスト（batch::build_bodyで組み立てたもの）
+    // レスポンスのContent-Typeとボディを返す
+    pub async fn call_batch(&self, url: &str, body: &str, quota_units: u32) -> Result<(String, String)> {
+        let content_type = format!("multipart/mixed; boundary={}", BOUNDARY);
+        self.execute(url, quota_units, |access_token| {
+            self.client.post(url)
+                .header(CONTENT_TYPE, content_type.as_str())
+                .body(body.to_string())
+                .bearer_auth(access_token)
+        }).await
+    }
+
+    // buildで作ったリクエストを送り、成功したらContent-Typeとボディを返す
+    async fn execute(
+        &self,
+        url: &str,
+        quota_units: u32,
+        build: impl Fn(&str) -> RequestBuilder,
+    ) -> Result<(String, String)>
+    {
+        let mut attempt = 0;
+        loop {
+            let res = self.send(quota_units, &build).await?;
+            let status = res.status();
+            let header = |name| res.headers().get(name).and_then(|value: &HeaderValue| value.to_str().ok());
+            let retry_after = header(RETRY_AFTER)
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs);
+            let content_type = header(CONTENT_TYPE).unwrap_or_default().to_string();
+            let res_body = res.text().await?;
+
+            if status.is_success() {
+                return Ok((content_type, res_body));
             }
+
+            // 403（権限・クォータ）などはGmail APIのエラーボディを読んで返す
+            // クォータ超過と5xxは間隔を空けて再送
+            let err = Error::from_response(status.as_u16(), url, &res_body);
+            if !err.is_retryable() || attempt >= MAX_RETRIES {
+                return Err(err);
+            }
+
+            delay_for(retry_after.unwrap_or_else(|| backoff(attempt))).await;
+            attempt += 1;
         }
     }
+
+    async fn send(&self, quota_units: u32, build: &impl Fn(&str) -> RequestBuilder) -> Result<Response> {
+        self.rate_limiter.acquire(quota_units).await;
+        let access_token = self.access_token().await?;
+        let mut res: Response = build(&access_token).send().await?;
+
+        // アクセストークンが失効していたら更新して1度だけ再送
+        if res.status() == StatusCode::UNAUTHORIZED {
+            self.refresh_if_unchanged(&access_token).await?;
+            let access_token = self.access_token().await?;
+            self.rate_limiter.acquire(quota_units).await;
+            res = build(&access_token).send().await?;
+        }
+
+        Ok(res)
+    }
+}
+
+// 再送までの待ち時間
+// 1, 2, 4 ... 32秒に、同時に失敗したリクエストがずれるよう1秒未満のゆらぎを足す
+fn backoff(attempt: u32) -> Duration {
+    let jitter = rand::thread_rng().gen_range(0, 1000);
+    Duration::from_secs(1 << attempt.min(5)) + Duration::from_millis(jitter)
 }
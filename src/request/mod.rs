@@ -1,2 +1,5 @@
+pub mod batch;
 pub mod client;
+pub mod rate_limit;
 pub mod secret;
+pub mod token;
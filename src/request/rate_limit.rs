@@ -0,0 +1,63 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::time::delay_for;
+
+// Gmail APIのクォータ単位で数えるトークンバケット
+// 1秒分（units_per_second）まで溜められ、使い切ったら溜まるまで待つ
+#[derive(Debug)]
+pub struct RateLimiter {
+    units_per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // 負の値は先に予約された分
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    // 0なら制限しない
+    pub fn new(units_per_second: u32) -> Self {
+        Self {
+            units_per_second: units_per_second as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: units_per_second as f64,
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    // unitsを先に差し引き、足りなかった分が溜まるまで待つ
+    // 待っている間に来た呼び出しはその後ろに並ぶ
+    pub async fn acquire(&self, units: u32) {
+        if self.units_per_second == 0.0 {
+            return;
+        }
+
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.units_per_second).min(self.units_per_second);
+            bucket.updated_at = now;
+            bucket.tokens -= units as f64;
+
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / self.units_per_second)
+        };
+
+        delay_for(wait).await;
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
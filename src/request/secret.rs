@@ -1,18 +1,50 @@
 extern crate serde;
 extern crate serde_json;
 
+use super::token::Token;
+use crate::error::{Error, Result};
 use url::Url;
+use serde::Deserialize;
+use chrono::{Duration, Utc};
 use oauth2::{
-    basic::BasicClient, reqwest::http_client, TokenResponse, StandardRevocableToken, RevocableToken,
+    basic::BasicClient, reqwest::http_client, TokenResponse,
     AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, RedirectUrl,
     RevocationUrl, Scope, TokenUrl,
 };
 use std::{
-    io::{BufRead, BufReader, Write, Error},
+    io::{BufRead, BufReader, Write},
     net::TcpListener,
     fs,
 };
 
+// OAuth2のエンドポイント
+// ローカルのモックサーバーに向けられるよう設定で差し替える
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    pub auth_url: String,
+    pub token_url: String,
+    pub revoke_url: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            auth_url: "https://accounts.google.com/o/oauth2/auth".to_string(),
+            token_url: "https://oauth2.googleapis.com/token".to_string(),
+            revoke_url: "https://oauth2.googleapis.com/revoke".to_string(),
+        }
+    }
+}
+
+// トークンエンドポイントのレスポンス
+#[derive(Deserialize, Debug)]
+struct RefreshResponse {
+    access_token: String,
+    expires_in: Option<i64>,
+    scope: Option<String>,
+    refresh_token: Option<String>,
+}
+
 struct Secret {
     client_id: String,
     project_id: String,
@@ -22,15 +54,17 @@ struct Secret {
     client_secret: String,
 }
 
-pub fn get_oauth2_token() -> Result<String, Error> {
+pub fn get_oauth2_token(endpoints: &Endpoints) -> Result<Token> {
     let secret = get_secret()?;
 
     let google_client_id = ClientId::new(secret.0);
     let google_client_secret = ClientSecret::new(secret.1);
-    let auth_url = AuthUrl::new("https://accounts.google.com/o/oauth2/auth".to_string())
-        .expect("Invalid authorization endpoint URL");
-    let token_url = TokenUrl::new("https://oauth2.googleapis.com/token".to_string())
-        .expect("Invalid token endpoint URL");
+    let auth_url = AuthUrl::new(endpoints.auth_url.clone())
+        .map_err(|_| Error::Auth(format!("invalid authorization endpoint URL: {}", endpoints.auth_url)))?;
+    let token_url = TokenUrl::new(endpoints.token_url.clone())
+        .map_err(|_| Error::Auth(format!("invalid token endpoint URL: {}", endpoints.token_url)))?;
+    let revocation_url = RevocationUrl::new(endpoints.revoke_url.clone())
+        .map_err(|_| Error::Auth(format!("invalid revocation endpoint URL: {}", endpoints.revoke_url)))?;
 
     let client = BasicClient::new(
         google_client_id,
@@ -41,10 +75,7 @@ pub fn get_oauth2_token() -> Result<String, Error> {
         .set_redirect_uri(
             RedirectUrl::new("http://localhost:8080".to_string()).expect("Invalid redirect URL"),
         )
-        .set_revocation_uri(
-            RevocationUrl::new("https://oauth2.googleapis.com/revoke".to_string())
-                .expect("Invalid revocation endpoint URL"),
-        );
+        .set_revocation_uri(revocation_url);
 
     let (pkce_code_challenge, pkce_code_verifier) = PkceCodeChallenge::new_random_sha256();
 
@@ -54,6 +85,9 @@ pub fn get_oauth2_token() -> Result<String, Error> {
             "https://mail.google.com/".to_string(),
         ))
         .set_pkce_challenge(pkce_code_challenge)
+        // リフレッシュトークンを毎回受け取るため
+        .add_extra_param("access_type", "offline")
+        .add_extra_param("prompt", "consent")
         .url();
 
     println!(
@@ -61,8 +95,8 @@ pub fn get_oauth2_token() -> Result<String, Error> {
         authorize_url.to_string()
     );
 
-    let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
-    let mut token = String::new();
+    let listener = TcpListener::bind("127.0.0.1:8080")?;
+    let mut token = Token::default();
 
     for stream in listener.incoming() {
         if let Ok(mut stream) = stream {
@@ -72,10 +106,12 @@ pub fn get_oauth2_token() -> Result<String, Error> {
                 let mut reader = BufReader::new(&stream);
 
                 let mut request_line = String::new();
-                reader.read_line(&mut request_line).unwrap();
+                reader.read_line(&mut request_line)?;
 
-                let redirect_url = request_line.split_whitespace().nth(1).unwrap();
-                let url = Url::parse(&("http://localhost".to_string() + redirect_url)).unwrap();
+                let redirect_url = request_line.split_whitespace().nth(1)
+                    .ok_or_else(|| Error::Auth("invalid redirect request".to_string()))?;
+                let url = Url::parse(&("http://localhost".to_string() + redirect_url))
+                    .map_err(|_| Error::Auth("invalid redirect request".to_string()))?;
 
                 let code_pair = url
                     .query_pairs()
@@ -83,7 +119,7 @@ pub fn get_oauth2_token() -> Result<String, Error> {
                         let &(ref key, _) = pair;
                         key == "code"
                     })
-                    .unwrap();
+                    .ok_or_else(|| Error::Auth("authorization code is not returned".to_string()))?;
 
                 let (_, value) = code_pair;
                 code = AuthorizationCode::new(value.into_owned());
@@ -94,7 +130,7 @@ pub fn get_oauth2_token() -> Result<String, Error> {
                         let &(ref key, _) = pair;
                         key == "state"
                     })
-                    .unwrap();
+                    .ok_or_else(|| Error::Auth("state is not returned".to_string()))?;
 
                 let (_, value) = state_pair;
                 state = CsrfToken::new(value.into_owned());
@@ -106,7 +142,11 @@ pub fn get_oauth2_token() -> Result<String, Error> {
                 message.len(),
                 message
             );
-            stream.write_all(response.as_bytes()).unwrap();
+            stream.write_all(response.as_bytes())?;
+
+            if state.secret() != csrf_state.secret() {
+                return Err(Error::Auth("state does not match".to_string()));
+            }
 
             // println!("Google returned the following code:\n{}\n", code.secret());
             // println!(
@@ -125,21 +165,19 @@ pub fn get_oauth2_token() -> Result<String, Error> {
             //     token_response
             // );
 
-            let token_response = token_response.unwrap();
-
-            let token_to_revoke: StandardRevocableToken = match token_response.refresh_token() {
-                Some(token) => {
-                    // println!("token: {}", token.secret());
-                    token.into()
-                },
-                None => {
-                    // println!("token: {}", token_response.access_token().secret());
-                    token_response.access_token().into()
-                },
+            let token_response = token_response.map_err(|err| Error::Auth(err.to_string()))?;
+
+            token = Token {
+                access_token: token_response.access_token().secret().to_string(),
+                refresh_token: token_response.refresh_token().map(|t| t.secret().to_string()),
+                expires_at: token_response.expires_in()
+                    .and_then(|d| Duration::from_std(d).ok())
+                    .map(|d| Utc::now() + d),
+                scopes: token_response.scopes()
+                    .map(|scopes| scopes.iter().map(|s| s.to_string()).collect())
+                    .unwrap_or_default(),
             };
 
-            token = token_to_revoke.secret().to_string();
-
             // revoke
             // client
             //     .revoke_token(token_to_revoke)
@@ -154,10 +192,57 @@ pub fn get_oauth2_token() -> Result<String, Error> {
     Ok(token)
 }
 
-fn get_secret() -> Result<(String, String), Error> {
-    let content = fs::read_to_string("./data/client_secret.json")?;
+// リフレッシュトークンでアクセストークンを更新
+pub async fn refresh_access_token(
+    client: &reqwest::Client,
+    endpoints: &Endpoints,
+    token: &Token,
+) -> Result<Token> {
+    // リフレッシュトークンがなければ認可し直すしかない
+    let refresh_token = token.refresh_token.as_ref().ok_or(Error::TokenRevoked)?;
+    let (client_id, client_secret) = get_secret()?;
+
+    let res = client
+        .post(&endpoints.token_url)
+        .form(&[
+            ("client_id", client_id.as_str()),
+            ("client_secret", client_secret.as_str()),
+            ("refresh_token", refresh_token.as_str()),
+            ("grant_type", "refresh_token"),
+        ])
+        .send().await?;
+
+    // 失効・取り消し済みのリフレッシュトークンは invalid_grant が返る
+    if let Some(err) = res.error_for_status_ref().err() {
+        let body = res.text().await?;
+        if body.contains("invalid_grant") {
+            return Err(Error::TokenRevoked);
+        }
+        return Err(err.into());
+    }
+
+    let res: RefreshResponse = res.json().await?;
+
+    Ok(Token {
+        access_token: res.access_token,
+        // 更新時は新しいリフレッシュトークンが返らないことが多い
+        refresh_token: res.refresh_token.or_else(|| token.refresh_token.clone()),
+        expires_at: res.expires_in.map(|secs| Utc::now() + Duration::seconds(secs)),
+        scopes: res.scope
+            .map(|scope| scope.split_whitespace().map(|s| s.to_string()).collect())
+            .unwrap_or_else(|| token.scopes.clone()),
+    })
+}
+
+fn get_secret() -> Result<(String, String)> {
+    let content = fs::read_to_string("./data/client_secret.json").map_err(Error::ClientSecret)?;
+
+    let p: serde_json::Value = serde_json::from_str(&content)?;
 
-    let p: serde_json::Value = serde_json::from_str(&content).unwrap();
+    let client_id = p["web"]["client_id"].as_str()
+        .ok_or_else(|| Error::Auth("client_id is not found in client_secret.json".to_string()))?;
+    let client_secret = p["web"]["client_secret"].as_str()
+        .ok_or_else(|| Error::Auth("client_secret is not found in client_secret.json".to_string()))?;
 
-    Ok((p["web"]["client_id"].as_str().unwrap().to_string(), p["web"]["client_secret"].as_str().unwrap().to_string()))
+    Ok((client_id.to_string(), client_secret.to_string()))
 }
@@ -0,0 +1,90 @@
+use serde::{Serialize, Deserialize};
+use chrono::{DateTime, Duration, Utc};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, Write},
+};
+
+// 期限切れ扱いにする猶予（秒）
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+// ./data/token に保存するトークン情報
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Token {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub scopes: Vec<String>,
+}
+
+impl Token {
+    // 旧形式（トークン文字列のみ）や空ファイルはNoneとして再認証させる
+    pub fn load(path: &str) -> Option<Token> {
+        let content = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    // 一時ファイルに書いてから置き換える（書き込み中に落ちてもリフレッシュトークンを失わないように）
+    // 本人以外は読めないよう0600で作る
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let tmp_path = format!("{}.tmp", path);
+        // 残っている一時ファイルは権限が違うかもしれないので作り直す
+        let _ = fs::remove_file(&tmp_path);
+
+        let mut file = create_private(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+
+    // 期限の少し前から期限切れとみなす
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => Utc::now() + Duration::seconds(EXPIRY_MARGIN_SECS) >= expires_at,
+            None => false,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn create_private(path: &str) -> Result<File, Error> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &str) -> Result<File, Error> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_replaces_token_readable_only_by_owner() {
+        let dir = std::env::temp_dir().join(format!("mark_as_read_token_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("token");
+        let path = path.to_str().unwrap();
+        fs::write(path, "old").unwrap();
+
+        let token = Token {
+            access_token: "access".to_string(),
+            refresh_token: Some("refresh".to_string()),
+            ..Token::default()
+        };
+        token.save(path).unwrap();
+
+        assert_eq!(Token::load(path), Some(token));
+        assert!(!dir.join("token.tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::data::write_atomic;
+use crate::error::{Error, Result};
+use crate::message::Message;
+use crate::sender::Sender;
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+use std::{fmt, fs};
+
+// ./data/mark_list.json の形式
+// version 1 はFromヘッダー文字列の配列だった
+pub const RULE_FILE_VERSION: u32 = 2;
+
+// 既読化の対象にする条件
+// 指定した条件すべてに一致したメッセージが対象
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Rule {
+    // 送信者アドレス、ドメイン全体なら "*@news.example.com"
+    // サブドメインも含めるなら "*@*.example.com"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    // 表示名の正規表現
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // 件名のキーワード（いずれかを含む）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subject: Vec<String>,
+    // 受信から何日以上経ったものか
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub older_than_days: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RuleFile {
+    version: u32,
+    rules: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyRuleFile {
+    Current(RuleFile),
+    Legacy(Vec<String>),
+}
+
+// 旧形式のファイルはFromヘッダーのアドレス部分のルールとして読む
+pub fn read_rules(path: &str) -> Result<Vec<Rule>> {
+    let read = fs::read_to_string(path)
+        .map_err(|source| Error::File { path: path.to_string(), source })?;
+    if read.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let file: AnyRuleFile = serde_json::from_str(&read)
+        .map_err(|source| Error::ParseFile { path: path.to_string(), source })?;
+
+    let rules = match file {
+        AnyRuleFile::Current(file) => file.rules,
+        AnyRuleFile::Legacy(list) => {
+            let mut rules = list.iter().map(|from| Rule::from_address(from)).collect::<Vec<Rule>>();
+            rules.dedup();
+            rules
+        }
+    };
+
+    Ok(rules)
+}
+
+pub fn write_rules(path: &str, rules: &Vec<Rule>) -> Result<()> {
+    let file = RuleFile {
+        version: RULE_FILE_VERSION,
+        rules: rules.clone(),
+    };
+    write_atomic(path, &serde_json::to_vec_pretty(&file)?)
+}
+
+impl Rule {
+    // Fromヘッダーの値からアドレス一致のルールを作る
+    pub fn from_address(from: &str) -> Self {
+        Self {
+            from: Some(Sender::parse(from).address),
+            ..Self::default()
+        }
+    }
+
+    // ワイルドカードなしの送信者アドレスだけの条件か
+    pub fn is_address(&self) -> bool {
+        match &self.from {
+            Some(from) => !from.contains('*') && *self == Self { from: Some(from.clone()), ..Self::default() },
+            None => false,
+        }
+    }
+
+    // "from:*@news.example.com subject:sale,coupon older:30" 形式
+    // 条件の書式でなければFromヘッダーの値とみなす
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let keys = ["from:", "name:", "subject:", "older:"];
+        if !keys.iter().any(|key| text.starts_with(key)) {
+            let rule = Self::from_address(text);
+            rule.check_input()?;
+            return Ok(rule);
+        }
+
+        let mut rule = Self::default();
+        for token in text.split_whitespace() {
+            let (key, value) = match token.find(':') {
+                Some(i) => (&token[..i], &token[i + 1..]),
+                None => return Err(Error::Rule(format!("missing condition name: {}", token))),
+            };
+            match key {
+                "from" => rule.from = Some(value.to_lowercase()),
+                "name" => rule.name = Some(value.to_string()),
+                "subject" => rule.subject = value.split(',')
+                    .filter(|k| !k.is_empty())
+                    .map(|k| k.to_string())
+                    .collect(),
+                "older" => rule.older_than_days = Some(value.trim_end_matches('d').parse()
+                    .map_err(|_| Error::Rule(format!("invalid number of days: {}", value)))?),
+                other => return Err(Error::Rule(format!("unknown condition: {}", other))),
+            }
+        }
+
+        rule.check_input()?;
+        Ok(rule)
+    }
+
+    // 入力されたルールだけ確かめる（保存済みのルールは読み込めなくならないように）
+    fn check_input(&self) -> Result<()> {
+        self.validate()?;
+        if let Some(from) = self.from.as_deref().filter(|from| !from.contains('@')) {
+            return Err(Error::Rule(format!("not an email address: {}", from)));
+        }
+        if let Some(days) = self.older_than_days.filter(|days| *days < 0) {
+            return Err(Error::Rule(format!("invalid number of days: {}", days)));
+        }
+        Ok(())
+    }
+
+    // 条件なしは全メッセージに一致してしまうので受け付けない
+    pub fn validate(&self) -> Result<()> {
+        self.matcher().map(|_| ())
+    }
+
+    pub fn matcher(&self) -> Result<Matcher<'_>> {
+        if self.from.is_none() && self.name.is_none() && self.subject.is_empty() && self.older_than_days.is_none() {
+            return Err(Error::Rule("a rule needs at least one condition".to_string()));
+        }
+        if self.from.as_deref().is_some_and(str::is_empty) {
+            return Err(Error::Rule("empty address".to_string()));
+        }
+
+        let name = match &self.name {
+            Some(name) => Some(Regex::new(name)
+                .map_err(|err| Error::Rule(format!("invalid name regex: {}", err)))?),
+            None => None,
+        };
+
+        Ok(Matcher {
+            rule: self,
+            from: self.from.as_ref().map(|from| from.to_lowercase()),
+            name,
+            now: Utc::now(),
+        })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut conditions = Vec::new();
+        if let Some(from) = &self.from {
+            conditions.push(format!("from:{}", from));
+        }
+        if let Some(name) = &self.name {
+            conditions.push(format!("name:{}", name));
+        }
+        if !self.subject.is_empty() {
+            conditions.push(format!("subject:{}", self.subject.join(",")));
+        }
+        if let Some(days) = self.older_than_days {
+            conditions.push(format!("older:{}d", days));
+        }
+
+        write!(f, "{}", conditions.join(" "))
+    }
+}
+
+// 正規表現をコンパイル済みのルール
+pub struct Matcher<'a> {
+    rule: &'a Rule,
+    // 小文字にしたアドレスの条件
+    from: Option<String>,
+    name: Option<Regex>,
+    now: DateTime<Utc>,
+}
+
+impl Matcher<'_> {
+    pub fn matches(&self, message: &Message) -> bool {
+        let sender = match message.sender() {
+            Some(sender) => sender,
+            None => return false,
+        };
+
+        if let Some(from) = &self.from {
+            let domain = sender.address.rsplit('@').next().unwrap_or("");
+            let matched = if let Some(site) = from.strip_prefix("*@*.") {
+                domain == site || domain.strip_suffix(site).is_some_and(|sub| sub.ends_with('.'))
+            } else if let Some(from_domain) = from.strip_prefix("*@") {
+                domain == from_domain
+            } else {
+                sender.address == *from
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        if let Some(regex) = &self.name {
+            if !regex.is_match(sender.name.as_deref().unwrap_or("")) {
+                return false;
+            }
+        }
+
+        if !self.rule.subject.is_empty() {
+            let subject = message.subject().unwrap_or_default().to_lowercase();
+            if !self.rule.subject.iter().any(|k| subject.contains(&k.to_lowercase())) {
+                return false;
+            }
+        }
+
+        if let Some(days) = self.rule.older_than_days {
+            match message.received_at() {
+                Some(received_at) if received_at <= self.now - Duration::days(days) => {},
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{Header, Payload};
+
+    fn message(from: &str, subject: &str, days_ago: i64) -> Message {
+        let header = |name: &str, value: &str| Header {
+            name: Some(name.to_string()),
+            value: Some(value.to_string()),
+        };
+        let received_at = Utc::now() - Duration::days(days_ago);
+
+        Message {
+            id: Some("1".to_string()),
+            thread_id: None,
+            label_ids: None,
+            history_id: None,
+            internal_date: Some(received_at.timestamp_millis().to_string()),
+            snippet: None,
+            payload: Some(Payload {
+                headers: Some(vec![header("From", from), header("Subject", subject)]),
+            }),
+        }
+    }
+
+    fn matches(rule: &str, message: &Message) -> bool {
+        Rule::parse(rule).unwrap().matcher().unwrap().matches(message)
+    }
+
+    #[test]
+    fn parses_conditions() {
+        let rule = Rule::parse(" from:*@News.Example.com name:^Amazon subject:sale,,coupon older:30d ").unwrap();
+
+        assert_eq!(rule, Rule {
+            from: Some("*@news.example.com".to_string()),
+            name: Some("^Amazon".to_string()),
+            subject: vec!["sale".to_string(), "coupon".to_string()],
+            older_than_days: Some(30),
+        });
+        assert_eq!(rule.to_string(), "from:*@news.example.com name:^Amazon subject:sale,coupon older:30d");
+        assert_eq!(Rule::parse(&rule.to_string()).unwrap(), rule);
+    }
+
+    #[test]
+    fn parses_from_header_as_address() {
+        let rule = Rule::parse("Foo <Foo@Example.com>").unwrap();
+
+        assert_eq!(rule, Rule::from_address("foo@example.com"));
+        assert_eq!(rule.to_string(), "from:foo@example.com");
+        assert!(rule.is_address());
+        assert!(!Rule::parse("from:*@example.com").unwrap().is_address());
+        assert!(!Rule::parse("from:foo@example.com older:3").unwrap().is_address());
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for text in &["", "from:", "from:foo@example.com bar", "older:3 to:foo@example.com", "name:(", "older:soon", "subject:",
+                      "foo", "from:foo", "older:-3d"] {
+            assert!(Rule::parse(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn matches_address_and_domains() {
+        let message = message("Foo <Foo@Mail.Example.com>", "hello", 0);
+
+        assert!(matches("from:foo@mail.example.com", &message));
+        assert!(matches("from:FOO@mail.example.com", &message));
+        assert!(!matches("from:foo@example.com", &message));
+        assert!(matches("from:*@mail.example.com", &message));
+        assert!(!matches("from:*@example.com", &message));
+        assert!(matches("from:*@*.example.com", &message));
+        assert!(matches("from:*@*.mail.example.com", &message));
+        assert!(!matches("from:*@*.ample.com", &message));
+    }
+
+    #[test]
+    fn matches_name_subject_and_age() {
+        let message = message("=?UTF-8?Q?Amazon=2Eco=2Ejp?= <store@amazon.co.jp>", "Big SALE today", 10);
+
+        assert!(matches("name:^Amazon", &message));
+        assert!(!matches("name:^amazon$", &message));
+        assert!(matches("subject:coupon,sale", &message));
+        assert!(!matches("subject:coupon", &message));
+        assert!(matches("older:7", &message));
+        assert!(!matches("older:30", &message));
+        // 条件はすべて満たしたときだけ
+        assert!(!matches("from:*@amazon.co.jp subject:coupon", &message));
+        assert!(matches("from:*@amazon.co.jp subject:sale older:10", &message));
+    }
+
+    #[test]
+    fn does_not_match_without_sender() {
+        let mut message = message("foo@example.com", "hello", 0);
+        message.payload = None;
+
+        assert!(!matches("subject:hello", &message));
+    }
+}
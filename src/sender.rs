@@ -0,0 +1,298 @@
+use std::fmt;
+
+// Fromヘッダーの送信者
+// "=?UTF-8?B?...?= <Foo@Example.com>" は name: デコードした表示名, address: foo@example.com
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Sender {
+    pub name: Option<String>,
+    // 小文字にしたアドレス
+    pub address: String,
+}
+
+impl Sender {
+    // RFC 5322 の mailbox として読む
+    // 複数のアドレスが並んでいる場合は最初のものだけ
+    pub fn parse(value: &str) -> Self {
+        let mut phrase = String::new();
+        let mut comment = String::new();
+        let mut angle_addr: Option<String> = None;
+
+        let mut chars = value.trim().chars();
+        while let Some(c) = chars.next() {
+            match c {
+                // quoted-string
+                '"' => {
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\\' => phrase.extend(chars.next()),
+                            '"' => break,
+                            c => phrase.push(c),
+                        }
+                    }
+                },
+                // comment（入れ子あり）
+                '(' => {
+                    let mut depth = 1;
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\\' => comment.extend(chars.next()),
+                            '(' => {
+                                depth += 1;
+                                comment.push(c);
+                            },
+                            ')' => {
+                                depth -= 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                                comment.push(c);
+                            },
+                            c => comment.push(c),
+                        }
+                    }
+                    phrase.push(' ');
+                },
+                '<' => {
+                    angle_addr = Some(chars.by_ref().take_while(|c| *c != '>').collect());
+                    break;
+                },
+                ',' => break,
+                c => phrase.push(c),
+            }
+        }
+
+        let (name, address) = match angle_addr {
+            Some(address) => (phrase, address),
+            // 表示名のない addr-spec だけの形
+            None => (String::new(), phrase),
+        };
+
+        // "<@route:foo@example.com>" の経路部分は捨てる
+        let address = address.rsplit(':').next().unwrap_or("").trim().to_lowercase();
+
+        let name = [name, comment].iter()
+            .map(|n| collapse_whitespace(&decode_header(n)))
+            .find(|n| !n.is_empty());
+
+        Self { name, address }
+    }
+}
+
+impl fmt::Display for Sender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} <{}>", name, self.address),
+            None => write!(f, "{}", self.address),
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// encoded-word（RFC 2047）を含むヘッダーの値をデコードする
+// 未対応の文字コードはそのまま残す
+pub fn decode_header(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    let mut after_encoded_word = false;
+
+    while let Some(start) = rest.find("=?") {
+        let (before, word) = rest.split_at(start);
+        match decode_encoded_word(word) {
+            Some((text, len)) => {
+                // encoded-word同士の間の空白は表示しない
+                if !(after_encoded_word && before.trim().is_empty()) {
+                    decoded.push_str(before);
+                }
+                decoded.push_str(&text);
+                rest = &word[len..];
+                after_encoded_word = true;
+            },
+            None => {
+                decoded.push_str(before);
+                decoded.push_str("=?");
+                rest = &word[2..];
+                after_encoded_word = false;
+            }
+        }
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+// "=?charset?encoding?text?=" をデコードし、読んだ長さと一緒に返す
+fn decode_encoded_word(word: &str) -> Option<(String, usize)> {
+    let mut parts = word.get(2..)?.splitn(3, '?');
+    let charset = parts.next()?;
+    let encoding = parts.next()?;
+    let rest = parts.next()?;
+    let end = rest.find("?=")?;
+    let text = &rest[..end];
+    if text.contains(char::is_whitespace) {
+        return None;
+    }
+    let len = 2 + charset.len() + 1 + encoding.len() + 1 + end + 2;
+
+    let bytes = match encoding {
+        "B" | "b" => decode_base64(text)?,
+        "Q" | "q" => decode_q(text)?,
+        _ => return None,
+    };
+
+    // "UTF-8*ja" のような言語指定は無視する
+    let charset = charset.split('*').next()?.to_ascii_lowercase();
+    let text = match charset.as_str() {
+        "utf-8" | "utf8" | "us-ascii" => String::from_utf8(bytes).ok()?,
+        "iso-8859-1" | "latin1" => bytes.iter().map(|b| *b as char).collect(),
+        _ => return None,
+    };
+
+    Some((text, len))
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in text.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(bytes)
+}
+
+// Q encoding は quoted-printable とほぼ同じで "_" が空白
+fn decode_q(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut iter = text.bytes();
+
+    while let Some(c) = iter.next() {
+        match c {
+            b'_' => bytes.push(b' '),
+            b'=' => {
+                let hex = [iter.next()?, iter.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            },
+            c => bytes.push(c),
+        }
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sender(name: Option<&str>, address: &str) -> Sender {
+        Sender {
+            name: name.map(|name| name.to_string()),
+            address: address.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_name_addr() {
+        assert_eq!(Sender::parse("Foo Bar <Foo@Example.com>"), sender(Some("Foo Bar"), "foo@example.com"));
+        assert_eq!(Sender::parse("foo@example.com"), sender(None, "foo@example.com"));
+    }
+
+    #[test]
+    fn parses_quoted_name_with_escapes() {
+        assert_eq!(
+            Sender::parse(r#""Foo \"Bar\" \\ Baz, Inc." <foo@example.com>"#),
+            sender(Some(r#"Foo "Bar" \ Baz, Inc."#), "foo@example.com"),
+        );
+    }
+
+    #[test]
+    fn uses_comment_of_addr_spec_as_name() {
+        assert_eq!(Sender::parse("foo@example.com (Foo Bar)"), sender(Some("Foo Bar"), "foo@example.com"));
+    }
+
+    #[test]
+    fn keeps_nested_comments() {
+        assert_eq!(
+            Sender::parse("foo@example.com (Foo (Tokyo \\) office) Bar)"),
+            sender(Some("Foo (Tokyo ) office) Bar"), "foo@example.com"),
+        );
+        // 表示名があればコメントは使わない
+        assert_eq!(Sender::parse("Foo (Bar) <foo@example.com>"), sender(Some("Foo"), "foo@example.com"));
+    }
+
+    #[test]
+    fn drops_route_of_route_addr() {
+        assert_eq!(
+            Sender::parse("Foo <@relay1.example.net,@relay2.example.net:foo@example.com>"),
+            sender(Some("Foo"), "foo@example.com"),
+        );
+    }
+
+    #[test]
+    fn takes_first_of_address_list() {
+        assert_eq!(Sender::parse("foo@example.com, bar@example.com"), sender(None, "foo@example.com"));
+    }
+
+    #[test]
+    fn drops_whitespace_between_adjacent_encoded_words() {
+        assert_eq!(decode_header("=?UTF-8?B?44K344On?= \r\n =?UTF-8?B?44OD44OX?="), "ショップ");
+        assert_eq!(decode_header("=?UTF-8?Q?a?= x =?UTF-8?Q?b?="), "a x b");
+        assert_eq!(decode_header("Re: =?UTF-8?Q?a?= =?UTF-8?Q?b?= !"), "Re: ab !");
+    }
+
+    #[test]
+    fn decodes_q_encoding() {
+        assert_eq!(decode_header("=?UTF-8?Q?caf=C3=A9_au_lait?="), "café au lait");
+        assert_eq!(decode_header("=?iso-8859-1?q?Andr=E9?="), "André");
+    }
+
+    #[test]
+    fn leaves_malformed_encoded_words() {
+        for text in &[
+            "50% =? off",
+            "=?UTF-8?B?44K344On",
+            "=?UTF-8?X?abc?=",
+            "=?UTF-8?Q?a b?=",
+            "=?UTF-8?Q?a=ZZ?=",
+            "=?UTF-8?B?!!!?=",
+            "=?Shift_JIS?B?g1aDh4Nig3Y=?=",
+        ] {
+            assert_eq!(decode_header(text), *text);
+        }
+        assert_eq!(decode_header("=?=?UTF-8?Q?a?="), "=?a");
+    }
+
+    #[test]
+    fn parses_encoded_word_senders_in_fixture() {
+        let fixture: Vec<serde_json::Value> = serde_json::from_str(include_str!("../fixtures/mailbox.json")).unwrap();
+        let senders = fixture.iter()
+            .map(|m| m["from"].as_str().unwrap())
+            .filter(|from| from.contains("=?"))
+            .map(Sender::parse)
+            .collect::<Vec<Sender>>();
+
+        assert_eq!(senders, vec![
+            sender(Some("ショップ"), "info@shop.example.co.jp"),
+            sender(Some("André Dupont"), "andre@example.fr"),
+        ]);
+    }
+}
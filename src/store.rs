@@ -0,0 +1,192 @@
+use crate::config::Config;
+use crate::data::{self, JournalEntry};
+use crate::error::{Error, Result};
+use crate::rule::{self, Rule};
+use chrono::Local;
+use fs2::FileExt;
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+};
+
+// Targetルール・ログ・履歴のメモリ上の状態
+// 起動時に一度だけ読み込み、変更したらそのファイルだけ書き戻す
+// ロックファイルを持っている間は他のプロセスが開けない
+pub struct Store<'a> {
+    mark_list_path: &'a str,
+    log_path: &'a str,
+    journal_path: &'a str,
+    rules: Vec<Rule>,
+    logs: Vec<String>,
+    journal: Vec<JournalEntry>,
+    dirty: Dirty,
+    // dropでロックが外れる
+    _lock: File,
+}
+
+// 書き戻せていないファイル
+#[derive(Default)]
+struct Dirty {
+    rules: bool,
+    logs: bool,
+    journal: bool,
+}
+
+impl<'a> Store<'a> {
+    pub fn open(config: &Config<'a>) -> Result<Self> {
+        let lock = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(config.lock_path)
+            .map_err(|source| Error::File { path: config.lock_path.to_string(), source })?;
+        lock.try_lock_exclusive()
+            .map_err(|_| Error::Locked(config.lock_path.to_string()))?;
+
+        Ok(Self {
+            mark_list_path: config.mark_list_path,
+            log_path: config.log_path,
+            journal_path: config.journal_path,
+            rules: rule::read_rules(config.mark_list_path)?,
+            logs: data::read_list(config.log_path)?,
+            journal: data::read_journal(config.journal_path)?,
+            dirty: Dirty::default(),
+            _lock: lock,
+        })
+    }
+
+    pub fn rules(&self) -> &Vec<Rule> {
+        &self.rules
+    }
+
+    // 同じルールがあれば追加しない
+    pub fn add_rule(&mut self, rule: Rule) -> Result<()> {
+        if !self.rules.contains(&rule) {
+            self.rules.push(rule);
+            self.dirty.rules = true;
+        }
+        self.flush()
+    }
+
+    pub fn remove_rule(&mut self, index: usize) -> Result<Option<Rule>> {
+        if index >= self.rules.len() {
+            return Ok(None);
+        }
+
+        let rule = self.rules.remove(index);
+        self.dirty.rules = true;
+        self.flush()?;
+
+        Ok(Some(rule))
+    }
+
+    // 条件に一致するルールをまとめて外し、外したルールを返す
+    pub fn remove_rules_where(&mut self, f: impl Fn(&Rule) -> bool) -> Result<Vec<Rule>> {
+        let (removed, kept) = self.rules.drain(..).partition(|rule| f(rule));
+        self.rules = kept;
+        if removed.is_empty() {
+            return Ok(removed);
+        }
+
+        self.dirty.rules = true;
+        self.flush()?;
+
+        Ok(removed)
+    }
+
+    pub fn logs(&self) -> &Vec<String> {
+        &self.logs
+    }
+
+    pub fn push_log(&mut self, kind: &str, body: &str) -> Result<()> {
+        self.logs.push(format!("[{}] {}: {}", Local::now().format("%Y年%m月%d日 %H:%M:%S"), kind, body));
+        self.dirty.logs = true;
+        self.flush()
+    }
+
+    pub fn journal(&self) -> &Vec<JournalEntry> {
+        &self.journal
+    }
+
+    // 既読にできたIDとその送信者を記録する
+    pub fn push_journal(&mut self, targets: &Vec<(String, Vec<String>)>, succeeded: &Vec<String>) -> Result<()> {
+        if succeeded.is_empty() {
+            return Ok(());
+        }
+
+        self.journal.push(JournalEntry {
+            timestamp: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            targets: targets.iter()
+                .filter(|(_, ids)| ids.iter().any(|id| succeeded.contains(id)))
+                .map(|(from, _)| from.clone())
+                .collect(),
+            ids: succeeded.clone(),
+            undone: false,
+        });
+        self.dirty.journal = true;
+        self.flush()
+    }
+
+    // Executeのチャンクごとに既読にできたIDを記録し、その履歴の位置を返す
+    // 最初のチャンクで履歴を作り、以降は同じ履歴に足していく（途中で終了しても取り消せるように）
+    pub fn journal_chunk(
+        &mut self,
+        index: Option<usize>,
+        targets: &Vec<(String, Vec<String>)>,
+        succeeded: &Vec<String>,
+    ) -> Result<Option<usize>> {
+        let entry = match index.and_then(|i| self.journal.get_mut(i)) {
+            Some(entry) => entry,
+            None if succeeded.is_empty() => return Ok(None),
+            None => {
+                self.push_journal(targets, succeeded)?;
+                return Ok(Some(self.journal.len() - 1));
+            },
+        };
+
+        entry.ids.extend(succeeded.iter().cloned());
+        let ids = entry.ids.iter().map(String::as_str).collect::<HashSet<&str>>();
+        entry.targets = targets.iter()
+            .filter(|(_, target_ids)| target_ids.iter().any(|id| ids.contains(id.as_str())))
+            .map(|(from, _)| from.clone())
+            .collect();
+        self.dirty.journal = true;
+        self.flush()?;
+
+        Ok(index)
+    }
+
+    pub fn set_undone(&mut self, index: usize) -> Result<()> {
+        if let Some(entry) = self.journal.get_mut(index) {
+            entry.undone = true;
+            self.dirty.journal = true;
+        }
+        self.flush()
+    }
+
+    // 変更のあったファイルを書き戻す
+    // 失敗したものは次の機会に書き直す
+    pub fn flush(&mut self) -> Result<()> {
+        if self.dirty.rules {
+            rule::write_rules(self.mark_list_path, &self.rules)?;
+            self.dirty.rules = false;
+        }
+        if self.dirty.logs {
+            data::write_list(self.log_path, &self.logs)?;
+            self.dirty.logs = false;
+        }
+        if self.dirty.journal {
+            data::write_journal(self.journal_path, &self.journal)?;
+            self.dirty.journal = false;
+        }
+
+        Ok(())
+    }
+}
+
+// 終了時に書き残しがあれば書き戻す
+impl Drop for Store<'_> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
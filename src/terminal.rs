@@ -0,0 +1,95 @@
+use crate::error::Result;
+use crossterm::{
+    cursor::Show,
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    io,
+    panic,
+    process,
+};
+use tokio::sync::mpsc;
+
+// rawモードと代替スクリーンを有効にし、dropで元に戻す
+// エラーで抜けた場合もpanicした場合もシェルが壊れたままにならないように
+pub struct TerminalGuard;
+
+impl TerminalGuard {
+    pub fn new() -> Result<Self> {
+        install_panic_hook();
+        enable_raw_mode()?;
+        // 代替スクリーンに切り替えられなくてもdropでrawモードを戻す
+        let guard = TerminalGuard;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+// 画面を元に戻す（何度呼んでもよい）
+pub fn restore() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, Show);
+}
+
+// 画面を戻してからpanicメッセージを出して終了する
+// tokio::spawnしたタスクのpanicはそのタスクだけが止まり、メインループが元の画面に描き続けるので
+// どこでpanicしてもプロセスごと終わらせる（履歴は変更のたびに書き戻している）
+fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        default_hook(info);
+        process::exit(101);
+    }));
+}
+
+// SIGINT/SIGTERMを受けるたびに終了コードを送る
+// 実行中のチャンクを待ってから終了できるよう、終了はメインループに任せる
+pub fn spawn_signal_handler() -> mpsc::UnboundedReceiver<i32> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let code = wait_for_signal().await;
+            if tx.send(code).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+// 終了コードは 128 + シグナル番号
+#[cfg(unix)]
+async fn wait_for_signal() -> i32 {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut interrupt, mut terminate) = match (
+        signal(SignalKind::interrupt()),
+        signal(SignalKind::terminate()),
+    ) {
+        (Ok(interrupt), Ok(terminate)) => (interrupt, terminate),
+        _ => return futures::future::pending().await,
+    };
+
+    tokio::select! {
+        _ = interrupt.recv() => 130,
+        _ = terminate.recv() => 143,
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> i32 {
+    if tokio::signal::ctrl_c().await.is_err() {
+        futures::future::pending::<()>().await;
+    }
+    130
+}
@@ -0,0 +1,207 @@
+mod common;
+
+use common::{data_dir, fake, ids, labels, mailbox, test_config};
+use crossterm::event::KeyCode;
+use mark_as_read::{
+    app::{App, TaskResult},
+    backend::{self, fake::FakeBackend},
+    cache::MetadataCache,
+    config::Config,
+    events::EventState,
+    rule::Rule,
+    store::Store,
+};
+use std::{fs, sync::Arc};
+use tokio::sync::mpsc;
+
+type Tasks = mpsc::UnboundedReceiver<TaskResult>;
+
+// ルールを登録し、キャッシュの内容で起動したApp
+// 裏で動かしたタスクの結果はTasksに届くので、テストから順にhandle_taskに渡す
+fn open<'a>(config: &'a Config<'a>, rules: &[&str], backend: Arc<FakeBackend>, cache: MetadataCache) -> (App<'a>, Tasks) {
+    let mut store = Store::open(config).unwrap();
+    for rule in rules {
+        store.add_rule(Rule::parse(rule).unwrap()).unwrap();
+    }
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    (App::new(config, store, backend, cache, tx), rx)
+}
+
+// FakeBackendのメールボックスを同期済みのApp
+async fn start<'a>(config: &'a Config<'a>, rules: &[&str], backend: FakeBackend) -> (App<'a>, Arc<FakeBackend>, Tasks) {
+    let backend = Arc::new(backend);
+    let cache = backend::sync_unread(backend.as_ref(), config.max_results, config.unread_limit, 4, &MetadataCache::default(), |_, _| {})
+        .await
+        .unwrap()
+        .cache;
+    let (app, tasks) = open(config, rules, backend.clone(), cache);
+
+    (app, backend, tasks)
+}
+
+// 同期が終わるまでタスクの結果を渡し、途中で届いたページ数を返す
+async fn finish_refresh(app: &mut App<'_>, tasks: &mut Tasks) -> usize {
+    let mut pages = 0;
+    loop {
+        let result = tasks.recv().await.unwrap();
+        let done = match result {
+            TaskResult::RefreshPage(_) => {
+                pages += 1;
+                false
+            },
+            TaskResult::Refresh(_) => true,
+            _ => false,
+        };
+        app.handle_task(result).unwrap();
+        if done {
+            return pages;
+        }
+    }
+}
+
+// 次のタスクの結果を渡す
+async fn next_task(app: &mut App<'_>, tasks: &mut Tasks) {
+    let result = tasks.recv().await.unwrap();
+    app.handle_task(result).unwrap();
+}
+
+// 実行中のExecute・Undoが終わるまでタスクの結果を渡す
+async fn finish_execution(app: &mut App<'_>, tasks: &mut Tasks) {
+    while app.is_executing() {
+        next_task(app, tasks).await;
+    }
+}
+
+// 1回のbatchModifyで既読にできる件数より多い未読
+fn large_mailbox(count: usize) -> FakeBackend {
+    FakeBackend::new((0..count)
+        .map(|i| fake(&format!("{:05}", i), "News <news@news.example.com>", "daily digest"))
+        .collect())
+}
+
+#[tokio::test]
+async fn q_quits_while_executing() {
+    let dir = data_dir("app_quit");
+    let config = test_config(&dir);
+    let (mut app, backend, mut tasks) = start(&config, &["from:*@news.example.com"], large_mailbox(1500)).await;
+
+    assert_eq!(app.event(KeyCode::Char('e')).unwrap(), EventState::Consumed);
+    assert!(app.is_executing());
+    // 実行中は他のキーは受け付けず、qだけ終了として返す
+    assert_eq!(app.event(KeyCode::Char('r')).unwrap(), EventState::Consumed);
+    assert_eq!(app.event(KeyCode::Char('q')).unwrap(), EventState::NotConsumed);
+
+    // 終了するときは残りのチャンクを取りやめ、実行中のチャンクだけ記録する
+    app.interrupt_execution();
+    finish_execution(&mut app, &mut tasks).await;
+    assert_eq!(app.store().journal().len(), 1);
+    assert_eq!(app.store().journal()[0].ids.len(), 1000);
+    assert_eq!(labels(&backend, "00999").await, vec!["INBOX"]);
+    assert_eq!(labels(&backend, "01000").await, vec!["INBOX", "UNREAD"]);
+    assert_eq!(app.messages().len(), 500);
+
+    drop(app);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn execute_continues_when_the_journal_cannot_be_written() {
+    let dir = data_dir("app_journal_error");
+    let config = test_config(&dir);
+    let (mut app, backend, mut tasks) = start(&config, &["from:*@news.example.com"], large_mailbox(1500)).await;
+    // 書き戻し先をディレクトリにして履歴の書き込みを失敗させる
+    fs::remove_file(config.journal_path).unwrap();
+    fs::create_dir(config.journal_path).unwrap();
+
+    app.event(KeyCode::Char('e')).unwrap();
+    let mut errors = 0;
+    while app.is_executing() {
+        let result = tasks.recv().await.unwrap();
+        if app.handle_task(result).is_err() {
+            errors += 1;
+        }
+    }
+
+    // 書き込みエラーは返しつつ、すべてのチャンクを処理して終わる
+    assert_eq!(errors, 2);
+    assert_eq!(labels(&backend, "01499").await, vec!["INBOX"]);
+    assert!(app.messages().is_empty());
+    assert_eq!(app.event(KeyCode::Char('q')).unwrap(), EventState::NotConsumed);
+
+    drop(app);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn full_sync_shows_pages_and_highlights_changed_senders() {
+    let dir = data_dir("app_full_sync");
+    let mut config = test_config(&dir);
+    config.max_results = 2;
+    let (mut app, mut tasks) = open(&config, &[], Arc::new(mailbox()), MetadataCache::default());
+
+    app.start_refresh();
+    let result = tasks.recv().await.unwrap();
+    assert!(matches!(result, TaskResult::RefreshPage(_)));
+    app.handle_task(result).unwrap();
+    // 全件取得が終わる前に最初のページが表示される
+    assert_eq!(ids(app.messages()), vec!["1", "2"]);
+
+    assert_eq!(finish_refresh(&mut app, &mut tasks).await, 1);
+    assert_eq!(ids(app.messages()), vec!["1", "2", "3", "5"]);
+    // 同期を始めたときと比べるので、先に届いたページの送信者も変化として出る
+    let mut changed = app.changed_senders().iter().cloned().collect::<Vec<String>>();
+    changed.sort();
+    assert_eq!(changed, vec!["bar@example.org", "foo@example.com", "news@news.example.com"]);
+    assert!(app.store().logs().last().unwrap().contains("4 unread messages (3 senders changed)"));
+
+    drop(app);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn execute_removes_address_rules_once_read() {
+    let dir = data_dir("app_remove_rules");
+    let config = test_config(&dir);
+    let (mut app, backend, mut tasks) = start(&config, &["foo@example.com", "from:*@news.example.com"], mailbox()).await;
+
+    app.event(KeyCode::Char('e')).unwrap();
+    finish_execution(&mut app, &mut tasks).await;
+
+    assert_eq!(labels(&backend, "1").await, vec!["INBOX"]);
+    assert_eq!(labels(&backend, "3").await, vec!["INBOX"]);
+    assert_eq!(ids(app.messages()), vec!["5"]);
+    // 全部既読にできたアドレスのルールだけ外し、ドメインのルールは残す
+    let rules = app.store().rules().iter().map(|rule| rule.to_string()).collect::<Vec<String>>();
+    assert_eq!(rules, vec!["from:*@news.example.com"]);
+    assert!(app.store().logs().iter().any(|log| log.contains("from:foo@example.com")));
+
+    drop(app);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn undo_marks_unread_and_fetches_messages_again() {
+    let dir = data_dir("app_undo");
+    let config = test_config(&dir);
+    let (mut app, backend, mut tasks) = start(&config, &["from:*@news.example.com"], mailbox()).await;
+
+    app.event(KeyCode::Char('e')).unwrap();
+    finish_execution(&mut app, &mut tasks).await;
+    assert_eq!(ids(app.messages()), vec!["1", "5"]);
+
+    app.event(KeyCode::Char('u')).unwrap();
+    finish_execution(&mut app, &mut tasks).await;
+    assert_eq!(labels(&backend, "2").await, vec!["INBOX", "UNREAD"]);
+    assert_eq!(labels(&backend, "3").await, vec!["INBOX", "UNREAD"]);
+    assert!(app.store().journal()[0].undone);
+
+    // 未読に戻したメッセージは裏で取得し直してリストに戻す
+    next_task(&mut app, &mut tasks).await;
+    let mut restored = ids(app.messages());
+    restored.sort();
+    assert_eq!(restored, vec!["1", "2", "3", "5"]);
+
+    drop(app);
+    let _ = fs::remove_dir_all(&dir);
+}
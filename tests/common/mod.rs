@@ -0,0 +1,82 @@
+// 結合テストで共有するメールボックスとデータディレクトリ
+// テストファイルごとに使うものが違うので未使用の警告は出さない
+#![allow(dead_code)]
+
+use mark_as_read::{
+    backend::{self, MailBackend, fake::{FakeBackend, FakeMessage}},
+    cache::MetadataCache,
+    config::Config,
+    message::Message,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+pub fn fake(id: &str, from: &str, subject: &str) -> FakeMessage {
+    FakeMessage {
+        id: id.to_string(),
+        from: from.to_string(),
+        subject: Some(subject.to_string()),
+        date: None,
+        snippet: None,
+        labels: vec!["INBOX".to_string(), "UNREAD".to_string()],
+    }
+}
+
+pub fn mailbox() -> FakeBackend {
+    let mut read = fake("4", "Foo <foo@example.com>", "already read");
+    read.labels = vec!["INBOX".to_string()];
+
+    FakeBackend::new(vec![
+        fake("1", "Foo <foo@example.com>", "hello"),
+        fake("2", "News <news@news.example.com>", "weekly sale"),
+        fake("3", "News <news@news.example.com>", "daily digest"),
+        read,
+        fake("5", "bar@example.org", "lunch?"),
+    ])
+}
+
+pub fn ids(list: &[Message]) -> Vec<String> {
+    list.iter().filter_map(|m| m.id.clone()).collect()
+}
+
+pub async fn labels(backend: &FakeBackend, id: &str) -> Vec<String> {
+    backend.get_metadata(id).await.unwrap().label_ids.unwrap_or_default()
+}
+
+pub async fn sync(backend: &dyn MailBackend, cache: &MetadataCache) -> MetadataCache {
+    let synced = backend::sync_unread(backend, 2, 100, 4, cache, |_, _| {}).await.unwrap();
+    assert!(synced.failed.is_empty());
+    synced.cache
+}
+
+// テストごとのデータディレクトリ
+pub fn data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("mark_as_read_{}_{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for file in &["mark_list.json", "log.json", "journal.json"] {
+        fs::write(dir.join(file), "").unwrap();
+    }
+    dir
+}
+
+// データファイルをすべてdirに置く設定
+// Configはパスを借用するので、テストの間だけ使うパスはリークさせておく
+pub fn test_config(dir: &Path) -> Config<'static> {
+    let path = |file: &str| -> &'static str {
+        Box::leak(dir.join(file).to_str().unwrap().to_string().into_boxed_str())
+    };
+
+    Config {
+        mark_list_path: path("mark_list.json"),
+        log_path: path("log.json"),
+        journal_path: path("journal.json"),
+        report_path: path("dry_run_report.txt"),
+        cache_path: path("metadata_cache.json"),
+        lock_path: path(".lock"),
+        ..Config::default()
+    }
+}
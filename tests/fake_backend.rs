@@ -0,0 +1,241 @@
+mod common;
+
+use common::{data_dir, fake, ids, labels, mailbox, sync, test_config};
+use futures::future::BoxFuture;
+use mark_as_read::{
+    backend::{self, LabelChange, MailBackend, fake::FakeBackend},
+    cache::MetadataCache,
+    cli,
+    error::{Error, Result},
+    message::{self, HistoryPage, Message, MessageListPage},
+    rule::Rule,
+    store::Store,
+};
+use std::{fs, sync::Mutex};
+
+// 指定したIDのメタデータの取得だけ失敗させる
+struct FailingParts {
+    inner: FakeBackend,
+    failing: Vec<&'static str>,
+}
+
+impl MailBackend for FailingParts {
+    fn list_unread<'a>(&'a self, page_token: Option<&'a str>, max_results: u32) -> BoxFuture<'a, Result<MessageListPage>> {
+        self.inner.list_unread(page_token, max_results)
+    }
+
+    fn get_metadata<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Message>> {
+        if self.failing.contains(&id) {
+            return Box::pin(async move { Err(Error::Batch(format!("broken part {}", id))) });
+        }
+        self.inner.get_metadata(id)
+    }
+
+    fn history_id<'a>(&'a self) -> BoxFuture<'a, Result<String>> {
+        self.inner.history_id()
+    }
+
+    fn list_history<'a>(&'a self, start_history_id: &'a str, page_token: Option<&'a str>) -> BoxFuture<'a, Result<HistoryPage>> {
+        self.inner.list_history(start_history_id, page_token)
+    }
+
+    fn modify_labels<'a>(&'a self, ids: &'a [&'a str], add: &'a [&'a str], remove: &'a [&'a str]) -> BoxFuture<'a, Result<()>> {
+        self.inner.modify_labels(ids, add, remove)
+    }
+}
+
+#[tokio::test]
+async fn full_sync_lists_unread_with_metadata() {
+    let backend = mailbox();
+
+    let synced = sync(&backend, &MetadataCache::default()).await;
+
+    assert_eq!(ids(&synced.messages()), vec!["1", "2", "3", "5"]);
+    assert!(synced.messages().iter().all(|m| m.sender().is_some()));
+    assert!(synced.history_id().is_some());
+}
+
+#[tokio::test]
+async fn full_sync_passes_each_page_with_metadata() {
+    let backend = mailbox();
+    let pages = Mutex::new(Vec::new());
+
+    backend::sync_unread(&backend, 2, 100, 4, &MetadataCache::default(), |page, count| {
+        assert!(page.iter().all(|m| m.sender().is_some()));
+        pages.lock().unwrap().push((ids(page), count));
+    }).await.unwrap();
+
+    assert_eq!(pages.into_inner().unwrap(), vec![
+        (vec!["1".to_string(), "2".to_string()], 2),
+        (vec!["3".to_string(), "5".to_string()], 4),
+    ]);
+}
+
+#[tokio::test]
+async fn incremental_sync_applies_label_changes() {
+    let backend = mailbox();
+    let synced = sync(&backend, &MetadataCache::default()).await;
+
+    backend.modify_labels(&["2"], &[], &["UNREAD"]).await.unwrap();
+    backend.modify_labels(&["4"], &["UNREAD"], &[]).await.unwrap();
+    let resynced = sync(&backend, &synced).await;
+
+    let mut resynced_ids = ids(&resynced.messages());
+    resynced_ids.sort();
+    assert_eq!(resynced_ids, vec!["1", "3", "4", "5"]);
+    // 履歴から加わったものもメタデータを取得している
+    assert!(resynced.get("4").and_then(|m| m.sender()).is_some());
+}
+
+#[tokio::test]
+async fn incremental_sync_matches_full_sync_for_spam_and_trash() {
+    let mut spam = fake("6", "Spammer <spam@example.net>", "you won");
+    spam.labels.push("SPAM".to_string());
+    let backend = FakeBackend::new(vec![
+        spam,
+        fake("1", "Foo <foo@example.com>", "hello"),
+        fake("2", "bar@example.org", "lunch?"),
+    ]);
+    let synced = sync(&backend, &MetadataCache::default()).await;
+    assert_eq!(ids(&synced.messages()), vec!["1", "2"]);
+
+    // 迷惑メールから戻したものは加わり、ゴミ箱に移したものは除かれる
+    backend.modify_labels(&["6"], &[], &["SPAM"]).await.unwrap();
+    backend.modify_labels(&["2"], &["TRASH"], &[]).await.unwrap();
+    let resynced = sync(&backend, &synced).await;
+    let full = sync(&backend, &MetadataCache::default()).await;
+
+    let mut resynced_ids = ids(&resynced.messages());
+    resynced_ids.sort();
+    let mut full_ids = ids(&full.messages());
+    full_ids.sort();
+    assert_eq!(resynced_ids, vec!["1", "6"]);
+    assert_eq!(resynced_ids, full_ids);
+}
+
+#[tokio::test]
+async fn incremental_sync_keeps_unread_limit() {
+    let backend = mailbox();
+    let synced = backend::sync_unread(&backend, 2, 3, 4, &MetadataCache::default(), |_, _| {}).await.unwrap().cache;
+    assert_eq!(ids(&synced.messages()), vec!["1", "2", "3"]);
+
+    backend.modify_labels(&["4"], &["UNREAD"], &[]).await.unwrap();
+    let resynced = backend::sync_unread(&backend, 2, 3, 4, &synced, |_, _| {}).await.unwrap().cache;
+
+    // 新しく未読になったものが先頭に入り、古いものが押し出される
+    assert_eq!(ids(&resynced.messages()), vec!["4", "1", "2"]);
+}
+
+#[tokio::test]
+async fn failed_parts_are_reported_and_fetched_again() {
+    let backend = FailingParts { inner: mailbox(), failing: vec!["3"] };
+
+    let synced = backend::sync_unread(&backend, 2, 100, 4, &MetadataCache::default(), |_, _| {}).await.unwrap();
+    let failed = synced.failed.iter().map(|(id, _)| id.as_str()).collect::<Vec<&str>>();
+    assert_eq!(failed, vec!["3"]);
+    // 失敗したものはIDだけ残っている
+    assert_eq!(ids(&synced.cache.messages()), vec!["1", "2", "3", "5"]);
+    assert!(synced.cache.get("3").unwrap().payload.is_none());
+
+    // 次の同期で取り直す
+    let backend = FailingParts { inner: backend.inner, failing: vec![] };
+    let resynced = sync(&backend, &synced.cache).await;
+    assert!(resynced.get("3").and_then(|m| m.sender()).is_some());
+}
+
+#[tokio::test]
+async fn deleted_messages_are_skipped() {
+    let backend = mailbox();
+
+    let fetched = backend::fetch_metadata(&backend, &["1".to_string(), "9".to_string()], 4).await.unwrap();
+    assert_eq!(ids(&fetched.messages), vec!["1"]);
+    assert!(fetched.not_found.contains("9"));
+    assert!(fetched.failed.is_empty());
+}
+
+#[tokio::test]
+async fn collect_targets_counts_each_message_once() {
+    let backend = mailbox();
+    let list = sync(&backend, &MetadataCache::default()).await.messages();
+    let rules = vec![
+        Rule::parse("from:*@news.example.com").unwrap(),
+        Rule::parse("subject:sale").unwrap(),
+        Rule::parse("foo@example.com").unwrap(),
+    ];
+
+    let targets = message::collect_targets(&list, &rules).unwrap();
+
+    assert_eq!(targets, vec![
+        ("from:*@news.example.com".to_string(), vec!["2".to_string(), "3".to_string()]),
+        ("subject:sale".to_string(), vec![]),
+        ("from:foo@example.com".to_string(), vec!["1".to_string()]),
+    ]);
+}
+
+#[tokio::test]
+async fn mark_read_and_unread_change_labels() {
+    let backend = mailbox();
+
+    LabelChange::MarkRead.apply(&backend, &["1", "5"]).await.unwrap();
+    assert_eq!(labels(&backend, "1").await, vec!["INBOX"]);
+    assert_eq!(labels(&backend, "5").await, vec!["INBOX"]);
+    assert_eq!(labels(&backend, "2").await, vec!["INBOX", "UNREAD"]);
+
+    LabelChange::MarkUnread.apply(&backend, &["1"]).await.unwrap();
+    assert_eq!(labels(&backend, "1").await, vec!["INBOX", "UNREAD"]);
+    assert_eq!(labels(&backend, "5").await, vec!["INBOX"]);
+}
+
+#[tokio::test]
+async fn run_journals_and_undo_restores() {
+    let dir = data_dir("run_undo");
+    let config = test_config(&dir);
+    let mut store = Store::open(&config).unwrap();
+    store.add_rule(Rule::parse("from:*@news.example.com").unwrap()).unwrap();
+
+    let backend = mailbox();
+    let list = sync(&backend, &MetadataCache::default()).await.messages();
+
+    cli::run(&config, &mut store, &backend, &list, false).await.unwrap();
+    assert_eq!(labels(&backend, "2").await, vec!["INBOX"]);
+    assert_eq!(labels(&backend, "3").await, vec!["INBOX"]);
+    assert_eq!(store.journal().len(), 1);
+    assert_eq!(store.journal()[0].ids, vec!["2", "3"]);
+
+    cli::undo(&mut store, &backend, None).await.unwrap();
+    assert_eq!(labels(&backend, "2").await, vec!["INBOX", "UNREAD"]);
+    assert_eq!(labels(&backend, "3").await, vec!["INBOX", "UNREAD"]);
+    assert!(store.journal()[0].undone);
+
+    drop(store);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn journal_grows_with_each_chunk() {
+    let dir = data_dir("journal_chunk");
+    let config = test_config(&dir);
+    let targets = vec![
+        ("from:*@news.example.com".to_string(), vec!["2".to_string(), "3".to_string()]),
+        ("from:foo@example.com".to_string(), vec!["1".to_string()]),
+    ];
+
+    let mut store = Store::open(&config).unwrap();
+    assert_eq!(store.journal_chunk(None, &targets, &vec![]).unwrap(), None);
+    let index = store.journal_chunk(None, &targets, &vec!["2".to_string()]).unwrap();
+    assert_eq!(index, Some(0));
+    // 最初のチャンクの後に終了しても、そこまでの分は記録されている
+    drop(store);
+    let mut store = Store::open(&config).unwrap();
+    assert_eq!(store.journal()[0].ids, vec!["2"]);
+    assert_eq!(store.journal()[0].targets, vec!["from:*@news.example.com"]);
+
+    let index = store.journal_chunk(index, &targets, &vec!["3".to_string(), "1".to_string()]).unwrap();
+    assert_eq!(index, Some(0));
+    assert_eq!(store.journal().len(), 1);
+    assert_eq!(store.journal()[0].ids, vec!["2", "3", "1"]);
+    assert_eq!(store.journal()[0].targets, vec!["from:*@news.example.com", "from:foo@example.com"]);
+
+    drop(store);
+    let _ = fs::remove_dir_all(&dir);
+}